@@ -0,0 +1,229 @@
+use crate::beam::greedy;
+use crate::search::SearchContext;
+use crate::{
+    lower_bound, CompactLayout, CompactState, Heuristic, LowerBound, SearchConfig, SearchReport,
+    SearchStats, SolveError, Solver, TransferAction, TubeState, TubeStateNode,
+};
+use std::collections::HashMap;
+
+/// Finds a solution quickly, then keeps looking for shorter ones
+///
+/// The first solution comes from `GreedyBestFirst`. After that a depth first branch and
+/// bound search looks for anything shorter, trying the moves the heuristic likes best
+/// first and cutting off any line of play whose moves so far plus `lower_bound` can't
+/// beat the best solution found. When that search runs out of states the best solution
+/// is known to be one of the shortest, and the report's `lower_bound` is set to its
+/// length so the gap is 0.
+///
+/// If a limit in the config is hit after the first solution was found, the best one so
+/// far is returned instead of the error. `solve_with_callback` also hands over each
+/// solution as soon as it is found, each one shorter than the last.
+///
+/// ```
+/// use tubes::{Anytime, Bfs, SearchConfig, Solver, TubeArray, TubeState};
+/// let contents = std::fs::read_to_string("levels/2.toml").unwrap();
+/// let state = TubeState::from_tube_array(toml::from_str::<TubeArray>(&contents).unwrap());
+/// let mut lengths = Vec::new();
+/// let report = Anytime::default().solve_with_callback(
+///     &state,
+///     &SearchConfig::default(),
+///     &mut |solution, _| lengths.push(solution.actions.len()),
+/// );
+/// let shortest = Bfs.solve(&state, &SearchConfig::default()).result.unwrap();
+/// assert_eq!(report.result.unwrap().actions.len(), shortest.actions.len());
+/// assert_eq!(report.lower_bound, Some(shortest.actions.len()));
+/// assert_eq!(lengths.last(), Some(&shortest.actions.len()));
+/// assert!(lengths.windows(2).all(|pair| pair[0] > pair[1]));
+/// ```
+pub struct Anytime {
+    pub heuristic: Box<dyn Heuristic>,
+}
+
+impl Default for Anytime {
+    fn default() -> Self {
+        return Anytime {
+            heuristic: Box::new(LowerBound),
+        };
+    }
+}
+
+impl Solver for Anytime {
+    fn name(&self) -> &str {
+        return "anytime";
+    }
+
+    fn solve(&self, initial_state: &TubeState, config: &SearchConfig) -> SearchReport {
+        return self.solve_with_callback(initial_state, config, &mut |_, _| {});
+    }
+}
+
+impl Anytime {
+    /// Like `solve`, but calls `on_solution` with every solution that is shorter than the
+    /// ones before it, along with the statistics at the time it was found
+    pub fn solve_with_callback(
+        &self,
+        initial_state: &TubeState,
+        config: &SearchConfig,
+        on_solution: &mut dyn FnMut(&TubeStateNode, &SearchStats),
+    ) -> SearchReport {
+        let mut search = SearchContext::new(config);
+        let result = anytime(
+            initial_state,
+            self.heuristic.as_ref(),
+            on_solution,
+            &mut search,
+        );
+        return search.finish(result);
+    }
+}
+
+fn anytime(
+    initial_state: &TubeState,
+    heuristic: &dyn Heuristic,
+    on_solution: &mut dyn FnMut(&TubeStateNode, &SearchStats),
+    search: &mut SearchContext,
+) -> Result<TubeStateNode, SolveError> {
+    let first = greedy(initial_state, heuristic, search)?;
+    on_solution(&first, search.stats_now());
+    let layout = CompactLayout::new(initial_state);
+    let mut improver = Improver {
+        best: first,
+        shallowest: HashMap::new(),
+        layout,
+        heuristic,
+        on_solution,
+    };
+    let initial_key = improver
+        .layout
+        .canonical(&improver.layout.encode(initial_state));
+    improver.shallowest.insert(initial_key, 0);
+    let mut actions = Vec::new();
+    match improver.search(initial_state, &mut actions, search) {
+        Ok(()) => {
+            search.lower_bound = Some(improver.best.actions.len());
+            return Ok(improver.best);
+        }
+        Err(SolveError::SearchLimit(_)) => return Ok(improver.best),
+        Err(e) => return Err(e),
+    }
+}
+
+/// The state of the branch and bound search that looks for shorter solutions
+struct Improver<'a> {
+    best: TubeStateNode,
+    /// The fewest transfers each state has been reached with so far, by packed canonical
+    /// form
+    shallowest: HashMap<CompactState, usize>,
+    layout: CompactLayout,
+    heuristic: &'a dyn Heuristic,
+    on_solution: &'a mut dyn FnMut(&TubeStateNode, &SearchStats),
+}
+
+impl Improver<'_> {
+    /// Searches below `state`, reached with `actions`, for solutions shorter than the
+    /// best one
+    ///
+    /// A state that was already reached in as few transfers is skipped, since anything
+    /// below it was searched then with a bound at least as loose as the current one.
+    fn search(
+        &mut self,
+        state: &TubeState,
+        actions: &mut Vec<TransferAction>,
+        search: &mut SearchContext,
+    ) -> Result<(), SolveError> {
+        if state.issolved() {
+            if actions.len() < self.best.actions.len() {
+                self.best = TubeStateNode {
+                    actions: actions.clone(),
+                    state: state.clone(),
+                };
+                (self.on_solution)(&self.best, search.stats_now());
+            }
+            return Ok(());
+        }
+        if actions.len() + lower_bound(state) as usize >= self.best.actions.len() {
+            return Ok(());
+        }
+        search.expand(actions.len())?;
+        let depth = actions.len() + 1;
+        let mut next_states = Vec::new();
+        for (action, next_state) in search.successors(state, actions.last()) {
+            search.generated();
+            let canonical = self.layout.canonical(&self.layout.encode(&next_state));
+            if self.shallowest.get(&canonical).is_some_and(|d| *d <= depth) {
+                search.duplicate();
+                continue;
+            }
+            self.shallowest.insert(canonical, depth);
+            next_states.push((self.heuristic.estimate(&next_state), action, next_state));
+        }
+        search.frontier(self.shallowest.len());
+        next_states.sort_by_key(|(estimate, _, _)| *estimate);
+        for (_, action, next_state) in next_states {
+            actions.push(action);
+            self.search(&next_state, actions, search)?;
+            actions.pop();
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod anytime_tests {
+    use super::*;
+    use crate::{load_level, replay, Bfs, Limit};
+
+    #[test]
+    fn test_solutions_get_shorter_until_optimal() {
+        let config = SearchConfig::default();
+        for level in 1..=8 {
+            let state = load_level(&format!("levels/{}.toml", level));
+            let shortest = Bfs.solve(&state, &config).result.unwrap().actions.len();
+            let mut solutions = Vec::new();
+            let report =
+                Anytime::default().solve_with_callback(&state, &config, &mut |solution, _| {
+                    solutions.push(solution.clone())
+                });
+            for solution in &solutions {
+                let states = replay(&state, &solution.actions).unwrap();
+                assert_eq!(states.last(), Some(&solution.state));
+                assert!(solution.state.issolved());
+            }
+            assert!(solutions
+                .windows(2)
+                .all(|pair| pair[0].actions.len() > pair[1].actions.len()));
+            let solution = report.result.unwrap();
+            assert_eq!(solution.actions.len(), shortest, "level {}", level);
+            assert_eq!(
+                solutions.last().map(|s| &s.actions),
+                Some(&solution.actions)
+            );
+            assert_eq!(report.lower_bound, Some(shortest));
+        }
+    }
+
+    #[test]
+    fn test_limits_return_the_best_solution_so_far() {
+        let state = load_level("levels/8.toml");
+        let first = crate::GreedyBestFirst::default()
+            .solve(&state, &SearchConfig::default())
+            .stats
+            .nodes_expanded;
+        let config = SearchConfig {
+            max_nodes: Some(first + 1),
+            ..SearchConfig::default()
+        };
+        let report = Anytime::default().solve(&state, &config);
+        let solution = report.result.unwrap();
+        assert!(solution.state.issolved());
+        assert_eq!(report.lower_bound, Some(lower_bound(&state) as usize));
+        let config = SearchConfig {
+            max_nodes: Some(1),
+            ..SearchConfig::default()
+        };
+        assert_eq!(
+            Anytime::default().solve(&state, &config).result.err(),
+            Some(SolveError::SearchLimit(Limit::Nodes(1)))
+        );
+    }
+}
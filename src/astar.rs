@@ -0,0 +1,312 @@
+use crate::compact::{CompactNode, MoveTree};
+use crate::search::SearchContext;
+use crate::{
+    CompactLayout, CompactState, CostModel, LiquidColor, SearchConfig, SearchReport, SolveError,
+    Solver, TubeState, TubeStateNode,
+};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// Returns a lower bound on the number of transfers needed to solve the state
+///
+/// Every boundary between two different colors stacked in a tube has to be broken up
+/// before the game is solved. A solved state also can't spread a color over more tubes
+/// than it could fill, even filling the smallest tubes first, so all but that many of
+/// the tubes it sits at the bottom of have to be poured out. A single transfer can only
+/// remove one boundary or merge one bottom block, so the sum of both counts never
+/// overestimates the real distance to a solved state, whatever sizes the tubes are.
+///
+/// ```
+/// use tubes::LiquidColor;
+/// use tubes::Tube;
+/// use tubes::TubeState;
+/// use tubes::lower_bound;
+/// let solved = TubeState {
+///     tubes: vec![
+///         Tube::from_vec(vec![LiquidColor::Blue, LiquidColor::Blue, LiquidColor::Blue, LiquidColor::Blue]),
+///         Tube::from_vec(vec![]),
+///     ],
+/// };
+/// let mixed = TubeState {
+///     tubes: vec![
+///         Tube::from_vec(vec![LiquidColor::Blue, LiquidColor::Red, LiquidColor::Blue, LiquidColor::Red]),
+///         Tube::from_vec(vec![LiquidColor::Red, LiquidColor::Blue, LiquidColor::Red, LiquidColor::Blue]),
+///         Tube::from_vec(vec![]),
+///     ],
+/// };
+/// assert_eq!(lower_bound(&solved), 0);
+/// assert_eq!(lower_bound(&mixed), 6);
+/// ```
+pub fn lower_bound(state: &TubeState) -> i32 {
+    let mut boundaries = 0;
+    let mut bottoms: HashMap<&LiquidColor, i32> = HashMap::new();
+    let mut layer_counts: HashMap<&LiquidColor, usize> = HashMap::new();
+    for tube in &state.tubes {
+        let mut previous = None;
+        for layer in tube.layers.iter().filter(|l| **l != LiquidColor::Empty) {
+            if let Some(previous) = previous {
+                if previous != layer {
+                    boundaries += 1;
+                }
+            }
+            *layer_counts.entry(layer).or_insert(0) += 1;
+            previous = Some(layer);
+        }
+        if let Some(bottom) = previous {
+            *bottoms.entry(bottom).or_insert(0) += 1;
+        }
+    }
+    let mut capacities: Vec<usize> = state.tubes.iter().map(|t| t.capacity()).collect();
+    capacities.sort();
+    let mut extra_bottoms = 0;
+    for (color, count) in bottoms {
+        let mut most_tubes = 0;
+        let mut filled = 0;
+        for capacity in &capacities {
+            if filled + capacity > layer_counts[color] {
+                break;
+            }
+            filled += capacity;
+            most_tubes += 1;
+        }
+        extra_bottoms += (count - most_tubes).max(0);
+    }
+    return boundaries + extra_bottoms;
+}
+
+struct AStarEntry {
+    estimate: u64,
+    cost: u64,
+    node: CompactNode,
+}
+
+impl PartialEq for AStarEntry {
+    fn eq(&self, other: &Self) -> bool {
+        return self.cmp(other) == Ordering::Equal;
+    }
+}
+
+impl Eq for AStarEntry {}
+
+impl PartialOrd for AStarEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl Ord for AStarEntry {
+    // BinaryHeap is a max heap so the lowest estimate has to compare as the greatest.
+    // Ties are broken in favor of costlier nodes since they are closer to a solution.
+    fn cmp(&self, other: &Self) -> Ordering {
+        return other
+            .estimate
+            .cmp(&self.estimate)
+            .then_with(|| self.cost.cmp(&other.cost));
+    }
+}
+
+/// Solves the game of tubes using A* search
+///
+/// States are expanded in order of moves made so far plus the `lower_bound` of the moves
+/// still needed. Because the bound never overestimates, the first solved state that is
+/// expanded is reached with the fewest possible transfers, just like `solve_bfs`, while
+/// far fewer states have to be looked at on the larger levels. Like `solve_bfs`, states are
+/// deduplicated by their canonical tube order and the same errors are returned.
+pub fn solve_astar(initial_state: &TubeState) -> Result<TubeStateNode, SolveError> {
+    return solve_astar_with_config(initial_state, &SearchConfig::default());
+}
+
+/// Solves the game of tubes using A* search, giving up when a limit in the config is hit
+pub fn solve_astar_with_config(
+    initial_state: &TubeState,
+    config: &SearchConfig,
+) -> Result<TubeStateNode, SolveError> {
+    return AStar.solve(initial_state, config).result;
+}
+
+/// A* search guided by `lower_bound`, which finds a solution with the fewest transfers
+pub struct AStar;
+
+impl Solver for AStar {
+    fn name(&self) -> &str {
+        return "astar";
+    }
+
+    fn solve(&self, initial_state: &TubeState, config: &SearchConfig) -> SearchReport {
+        let mut search = SearchContext::new(config);
+        let result = astar(initial_state, CostModel::Pours, true, &mut search);
+        return search.finish(result);
+    }
+}
+
+/// A* search that minimizes the total cost of the moves under a `CostModel`
+///
+/// With `CostModel::Pours` this is the same as `AStar`. The other models trade extra
+/// pours for less liquid moved or for fewer pours into empty tubes.
+///
+/// ```
+/// use tubes::{CostModel, SearchConfig, Solver, TubeArray, TubeState, WeightedAStar};
+/// let contents = std::fs::read_to_string("levels/2.toml").unwrap();
+/// let state = TubeState::from_tube_array(toml::from_str::<TubeArray>(&contents).unwrap());
+/// let solver = WeightedAStar { cost_model: CostModel::Units };
+/// let solution = solver.solve(&state, &SearchConfig::default()).result.unwrap();
+/// assert!(solution.state.issolved());
+/// ```
+pub struct WeightedAStar {
+    pub cost_model: CostModel,
+}
+
+impl Solver for WeightedAStar {
+    fn name(&self) -> &str {
+        return "weighted-astar";
+    }
+
+    fn solve(&self, initial_state: &TubeState, config: &SearchConfig) -> SearchReport {
+        let mut search = SearchContext::new(config);
+        let result = astar(initial_state, self.cost_model, true, &mut search);
+        return search.finish(result);
+    }
+}
+
+/// Uniform-cost search, Dijkstra's algorithm, that minimizes the total cost of the moves
+/// under a `CostModel`
+///
+/// This is `WeightedAStar` without `lower_bound`: states are expanded in order of the cost
+/// of reaching them alone. It expands more states, but doesn't rely on the bound being
+/// admissible for the cost model, which makes it a check on `WeightedAStar`. With
+/// `CostModel::Pours` it expands states in the same order as `Bfs`.
+///
+/// ```
+/// use tubes::{CostModel, SearchConfig, Solver, TubeArray, TubeState, UniformCost, WeightedAStar};
+/// let contents = std::fs::read_to_string("levels/2.toml").unwrap();
+/// let state = TubeState::from_tube_array(toml::from_str::<TubeArray>(&contents).unwrap());
+/// let config = SearchConfig::default();
+/// let report = UniformCost { cost_model: CostModel::Units }.solve(&state, &config);
+/// let astar_report = WeightedAStar { cost_model: CostModel::Units }.solve(&state, &config);
+/// let rules = config.rules;
+/// assert_eq!(
+///     CostModel::Units.solution_cost(&state, &report.result.unwrap().actions, rules),
+///     CostModel::Units.solution_cost(&state, &astar_report.result.unwrap().actions, rules)
+/// );
+/// assert!(report.stats.nodes_expanded >= astar_report.stats.nodes_expanded);
+/// ```
+pub struct UniformCost {
+    pub cost_model: CostModel,
+}
+
+impl Default for UniformCost {
+    fn default() -> Self {
+        return UniformCost {
+            cost_model: CostModel::Pours,
+        };
+    }
+}
+
+impl Solver for UniformCost {
+    fn name(&self) -> &str {
+        return "uniform-cost";
+    }
+
+    fn solve(&self, initial_state: &TubeState, config: &SearchConfig) -> SearchReport {
+        let mut search = SearchContext::new(config);
+        let result = astar(initial_state, self.cost_model, false, &mut search);
+        return search.finish(result);
+    }
+}
+
+/// Best first search on the cost so far, plus the cost model's lower bound if
+/// `use_lower_bound` is set
+///
+/// Like `bfs`, states are kept packed and the moves that reach them in a `MoveTree`, so
+/// a state waiting in the queue doesn't carry a copy of every move before it.
+fn astar(
+    initial_state: &TubeState,
+    cost_model: CostModel,
+    use_lower_bound: bool,
+    search: &mut SearchContext,
+) -> Result<TubeStateNode, SolveError> {
+    initial_state.validate()?;
+    let bound = |state: &TubeState| {
+        if use_lower_bound {
+            return cost_model.lower_bound(state);
+        }
+        return 0;
+    };
+    let layout = CompactLayout::new(initial_state);
+    let mut tree = MoveTree::new();
+    let mut explored: HashSet<CompactState> = HashSet::new();
+    let mut open: BinaryHeap<AStarEntry> = BinaryHeap::new();
+    open.push(AStarEntry {
+        estimate: bound(initial_state),
+        cost: 0,
+        node: CompactNode::root(layout.encode(initial_state)),
+    });
+    while let Some(entry) = open.pop() {
+        let node = entry.node;
+        if !explored.insert(layout.canonical(&node.state)) {
+            search.duplicate();
+            continue;
+        }
+        if layout.issolved(&node.state) {
+            return Ok(TubeStateNode {
+                actions: tree.actions(node.node),
+                state: layout.decode(&node.state),
+            });
+        }
+        search.expand(node.depth as usize)?;
+        let state = layout.decode(&node.state);
+        let previous = tree.last_move(node.node);
+        for (action, next) in layout.successors(&node.state, previous.as_ref(), search.config()) {
+            search.generated();
+            if explored.contains(&layout.canonical(&next)) {
+                search.duplicate();
+                continue;
+            }
+            let next_state = layout.decode(&next);
+            let cost = entry.cost + cost_model.cost(&state, &action, &next_state);
+            open.push(AStarEntry {
+                estimate: cost + bound(&next_state),
+                cost,
+                node: CompactNode {
+                    node: tree.push(node.node, &action),
+                    depth: node.depth + 1,
+                    state: next,
+                },
+            });
+        }
+        search.frontier(open.len());
+    }
+    return Err(SolveError::Unsolvable);
+}
+
+#[cfg(test)]
+mod astar_tests {
+    use super::*;
+    use crate::{load_level, solve_bfs, Tube};
+
+    #[test]
+    fn test_lower_bound_is_admissible_on_level_2() {
+        let state = load_level("levels/2.toml");
+        let solution = solve_bfs(&state).unwrap();
+        assert!(lower_bound(&state) <= solution.actions.len() as i32);
+    }
+
+    #[test]
+    fn test_astar_matches_bfs_length() {
+        let state = load_level("levels/2.toml");
+        let bfs_solution = solve_bfs(&state).unwrap();
+        let astar_solution = solve_astar(&state).unwrap();
+        assert!(astar_solution.state.issolved());
+        assert_eq!(astar_solution.actions.len(), bfs_solution.actions.len());
+    }
+
+    #[test]
+    fn test_astar_already_solved() {
+        let state = TubeState {
+            tubes: vec![Tube::from_vec(vec![]), Tube::from_vec(vec![])],
+        };
+        let solution = solve_astar(&state).unwrap();
+        assert_eq!(solution.actions.len(), 0);
+        assert_eq!(solution.state, state);
+    }
+}
@@ -0,0 +1,311 @@
+use crate::compact::{CompactNode, MoveTree};
+use crate::search::SearchContext;
+use crate::{
+    lower_bound, CompactLayout, CompactState, Limit, SearchConfig, SearchReport, SolveError,
+    Solver, TubeState, TubeStateNode,
+};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+
+/// How many states `BeamSearch` keeps at each depth by default
+pub const DEFAULT_BEAM_WIDTH: usize = 100;
+
+/// An estimate of how many transfers a state is from being solved
+///
+/// `BeamSearch` and `GreedyBestFirst` expand the states with the lowest estimates first.
+/// The estimate doesn't have to be a lower bound, and any function from a state to a
+/// number of transfers can be used as one.
+///
+/// ```
+/// use tubes::{Heuristic, TubeState};
+/// let nonempty = |state: &TubeState| state.tubes.iter().filter(|t| !t.isempty()).count();
+/// let state = TubeState { tubes: vec![] };
+/// assert_eq!(nonempty.estimate(&state), 0);
+/// ```
+pub trait Heuristic: Send + Sync {
+    fn estimate(&self, state: &TubeState) -> usize;
+}
+
+impl<F> Heuristic for F
+where
+    F: Fn(&TubeState) -> usize + Send + Sync,
+{
+    fn estimate(&self, state: &TubeState) -> usize {
+        return self(state);
+    }
+}
+
+/// Uses `lower_bound` as the estimate
+pub struct LowerBound;
+
+impl Heuristic for LowerBound {
+    fn estimate(&self, state: &TubeState) -> usize {
+        return lower_bound(state) as usize;
+    }
+}
+
+/// Breadth first search that only keeps the `width` most promising states at each depth
+///
+/// Each step expands every state in the beam and keeps the `width` new states with the
+/// lowest estimates from the heuristic, so the work per step never grows. Solutions
+/// come quickly but aren't always the shortest, and a level can go unsolved if the beam
+/// throws away every state that leads to a solution. Then the error is
+/// `SolveError::SearchLimit(Limit::Beam(width))` rather than `SolveError::Unsolvable`,
+/// unless no state was ever thrown away. The report's `lower_bound` says how far from
+/// the shortest solution the one found can be.
+///
+/// ```
+/// use tubes::{BeamSearch, SearchConfig, Solver, TubeArray, TubeState};
+/// let contents = std::fs::read_to_string("levels/2.toml").unwrap();
+/// let state = TubeState::from_tube_array(toml::from_str::<TubeArray>(&contents).unwrap());
+/// let solver = BeamSearch { width: 10, ..BeamSearch::default() };
+/// let report = solver.solve(&state, &SearchConfig::default());
+/// let solution = report.result.as_ref().unwrap();
+/// assert!(solution.state.issolved());
+/// assert_eq!(report.gap(), Some(solution.actions.len() - 6));
+/// ```
+pub struct BeamSearch {
+    pub width: usize,
+    pub heuristic: Box<dyn Heuristic>,
+}
+
+impl Default for BeamSearch {
+    fn default() -> Self {
+        return BeamSearch {
+            width: DEFAULT_BEAM_WIDTH,
+            heuristic: Box::new(LowerBound),
+        };
+    }
+}
+
+impl Solver for BeamSearch {
+    fn name(&self) -> &str {
+        return "beam";
+    }
+
+    fn solve(&self, initial_state: &TubeState, config: &SearchConfig) -> SearchReport {
+        let mut search = SearchContext::new(config);
+        let result = beam(initial_state, self, &mut search);
+        return search.finish(result);
+    }
+}
+
+fn beam(
+    initial_state: &TubeState,
+    solver: &BeamSearch,
+    search: &mut SearchContext,
+) -> Result<TubeStateNode, SolveError> {
+    initial_state.validate()?;
+    search.lower_bound = Some(lower_bound(initial_state) as usize);
+    if initial_state.issolved() {
+        return Ok(TubeStateNode {
+            actions: vec![],
+            state: initial_state.clone(),
+        });
+    }
+    let width = solver.width.max(1);
+    let layout = CompactLayout::new(initial_state);
+    let mut tree = MoveTree::new();
+    let root = CompactNode::root(layout.encode(initial_state));
+    let mut explored: HashSet<CompactState> = HashSet::new();
+    explored.insert(layout.canonical(&root.state));
+    let mut layer = vec![root];
+    let mut dropped_any = false;
+    let mut depth = 0;
+    while !layer.is_empty() {
+        let mut candidates = Vec::new();
+        for node in &layer {
+            search.expand(depth)?;
+            let previous = tree.last_move(node.node);
+            for (action, state) in
+                layout.successors(&node.state, previous.as_ref(), search.config())
+            {
+                search.generated();
+                if !explored.insert(layout.canonical(&state)) {
+                    search.duplicate();
+                    continue;
+                }
+                let next = CompactNode {
+                    node: tree.push(node.node, &action),
+                    depth: node.depth + 1,
+                    state,
+                };
+                let next_state = layout.decode(&next.state);
+                if next_state.issolved() {
+                    return Ok(TubeStateNode {
+                        actions: tree.actions(next.node),
+                        state: next_state,
+                    });
+                }
+                candidates.push((solver.heuristic.estimate(&next_state), next));
+            }
+        }
+        // The sort is stable, so states with the same estimate keep the order they were
+        // generated in
+        candidates.sort_by_key(|(estimate, _)| *estimate);
+        if candidates.len() > width {
+            dropped_any = true;
+            candidates.truncate(width);
+        }
+        layer = candidates.into_iter().map(|(_, node)| node).collect();
+        search.frontier(layer.len());
+        depth += 1;
+    }
+    if dropped_any {
+        return Err(SolveError::SearchLimit(Limit::Beam(width)));
+    }
+    return Err(SolveError::Unsolvable);
+}
+
+/// Always expands the state the heuristic thinks is closest to solved
+///
+/// Unlike A* the moves made so far don't count, so the search heads straight for a
+/// solved state without checking whether a shorter way there exists. Every reachable
+/// state is eventually tried, so unlike `BeamSearch` a level that can be solved always
+/// is. The report's `lower_bound` says how far from the shortest solution the one found
+/// can be.
+pub struct GreedyBestFirst {
+    pub heuristic: Box<dyn Heuristic>,
+}
+
+impl Default for GreedyBestFirst {
+    fn default() -> Self {
+        return GreedyBestFirst {
+            heuristic: Box::new(LowerBound),
+        };
+    }
+}
+
+impl Solver for GreedyBestFirst {
+    fn name(&self) -> &str {
+        return "greedy";
+    }
+
+    fn solve(&self, initial_state: &TubeState, config: &SearchConfig) -> SearchReport {
+        let mut search = SearchContext::new(config);
+        let result = greedy(initial_state, self.heuristic.as_ref(), &mut search);
+        return search.finish(result);
+    }
+}
+
+pub(crate) fn greedy(
+    initial_state: &TubeState,
+    heuristic: &dyn Heuristic,
+    search: &mut SearchContext,
+) -> Result<TubeStateNode, SolveError> {
+    initial_state.validate()?;
+    search.lower_bound = Some(lower_bound(initial_state) as usize);
+    let layout = CompactLayout::new(initial_state);
+    let mut tree = MoveTree::new();
+    let root = CompactNode::root(layout.encode(initial_state));
+    let mut explored: HashSet<CompactState> = HashSet::new();
+    explored.insert(layout.canonical(&root.state));
+    // The heap holds the estimate and index of each node waiting to be expanded, and
+    // among equal estimates the newest node comes first
+    let mut nodes = vec![Some(root)];
+    let mut open = BinaryHeap::new();
+    open.push(Reverse((heuristic.estimate(initial_state), Reverse(0))));
+    while let Some(Reverse((_, Reverse(idx)))) = open.pop() {
+        let node = nodes[idx].take().unwrap();
+        if layout.issolved(&node.state) {
+            return Ok(TubeStateNode {
+                actions: tree.actions(node.node),
+                state: layout.decode(&node.state),
+            });
+        }
+        search.expand(node.depth as usize)?;
+        let previous = tree.last_move(node.node);
+        for (action, state) in layout.successors(&node.state, previous.as_ref(), search.config()) {
+            search.generated();
+            if !explored.insert(layout.canonical(&state)) {
+                search.duplicate();
+                continue;
+            }
+            let estimate = heuristic.estimate(&layout.decode(&state));
+            open.push(Reverse((estimate, Reverse(nodes.len()))));
+            nodes.push(Some(CompactNode {
+                node: tree.push(node.node, &action),
+                depth: node.depth + 1,
+                state,
+            }));
+        }
+        search.frontier(open.len());
+    }
+    return Err(SolveError::Unsolvable);
+}
+
+#[cfg(test)]
+mod beam_tests {
+    use super::*;
+    use crate::{load_level, replay, Bfs, TubeArray};
+
+    #[test]
+    fn test_quick_solutions_are_valid_and_bounded() {
+        let config = SearchConfig::default();
+        let solvers: Vec<Box<dyn Solver>> = vec![
+            Box::new(BeamSearch::default()),
+            Box::new(GreedyBestFirst::default()),
+        ];
+        for level in 1..=8 {
+            let state = load_level(&format!("levels/{}.toml", level));
+            let shortest = Bfs.solve(&state, &config).result.unwrap().actions.len();
+            for solver in &solvers {
+                let report = solver.solve(&state, &config);
+                let solution = report.result.as_ref().unwrap();
+                let states = replay(&state, &solution.actions).unwrap();
+                assert_eq!(states.last(), Some(&solution.state));
+                assert!(solution.state.issolved());
+                assert!(solution.actions.len() >= shortest);
+                let bound = report.lower_bound.unwrap();
+                assert!(bound <= shortest);
+                assert_eq!(report.gap(), Some(solution.actions.len() - bound));
+            }
+        }
+    }
+
+    #[test]
+    fn test_unlimited_beam_is_breadth_first() {
+        // With no limit on the width nothing is dropped, so the beam is a plain bfs
+        let solver = BeamSearch {
+            width: usize::MAX,
+            heuristic: Box::new(|_: &TubeState| 0),
+        };
+        let config = SearchConfig::default();
+        for level in [3, 6, 8] {
+            let state = load_level(&format!("levels/{}.toml", level));
+            let expected = Bfs.solve(&state, &config).result.unwrap().actions.len();
+            let solution = solver.solve(&state, &config).result.unwrap();
+            assert_eq!(solution.actions.len(), expected);
+        }
+    }
+
+    #[test]
+    fn test_narrow_beam_can_give_up() {
+        let config = SearchConfig::default();
+        let contents = "tubes = [['blue', 'red', 'blue', 'red'], ['red', 'blue', 'red', 'blue']]";
+        let tube_array: TubeArray = toml::from_str(contents).unwrap();
+        let stuck = TubeState::from_tube_array(tube_array);
+        let narrow = BeamSearch {
+            width: 1,
+            ..BeamSearch::default()
+        };
+        assert_eq!(
+            narrow.solve(&stuck, &config).result.err(),
+            Some(SolveError::Unsolvable)
+        );
+        assert_eq!(
+            GreedyBestFirst::default()
+                .solve(&stuck, &config)
+                .result
+                .err(),
+            Some(SolveError::Unsolvable)
+        );
+        for level in 1..=8 {
+            let state = load_level(&format!("levels/{}.toml", level));
+            match narrow.solve(&state, &config).result {
+                Ok(solution) => assert!(solution.state.issolved()),
+                Err(e) => assert_eq!(e, SolveError::SearchLimit(Limit::Beam(1))),
+            }
+        }
+    }
+}
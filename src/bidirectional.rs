@@ -0,0 +1,366 @@
+use crate::compact::{CompactNode, MoveTree};
+use crate::pruning::is_pruned;
+use crate::search::SearchContext;
+use crate::{
+    CompactLayout, CompactState, LiquidColor, Rules, SearchConfig, SearchReport, SolveError,
+    Solver, TransferAction, TubeState, TubeStateNode,
+};
+use std::collections::HashMap;
+
+/// Returns the states that reach the given state with one transfer
+///
+/// This undoes `neighbors_with_rules`: for every `(action, previous_state)` returned,
+/// applying `action` to `previous_state` under `rules` gives back `state`. A whole block
+/// pour moves the whole top block of the sending tube, so liquid can only be poured back
+/// into a tube whose top is a different color, unless a partial pour filled the receiving
+/// tube and left some behind. A receiving tube can only be emptied completely if it was
+/// empty before the pour.
+///
+/// ```
+/// use tubes::LiquidColor;
+/// use tubes::Tube;
+/// use tubes::TubeState;
+/// use tubes::{neighbors, reverse_neighbors, Rules};
+/// let state = TubeState {
+///     tubes: vec![
+///         Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Blue]),
+///         Tube::from_vec(vec![LiquidColor::Orange]),
+///     ],
+/// };
+/// for (action, next_state) in neighbors(&state) {
+///     assert!(reverse_neighbors(&next_state, Rules::WholeBlock).contains(&(action, state.clone())));
+/// }
+/// ```
+pub fn reverse_neighbors(state: &TubeState, rules: Rules) -> Vec<(TransferAction, TubeState)> {
+    let mut previous_states = Vec::new();
+    for (ri, recv_tube) in state.tubes.iter().enumerate() {
+        if recv_tube.isempty() {
+            continue;
+        }
+        let (run, color) = recv_tube.topcolor();
+        let recv_empty = recv_tube.howempty();
+        let recv_height = recv_tube.layers.len() as i32 - recv_empty;
+        for (si, send_tube) in state.tubes.iter().enumerate() {
+            if si == ri {
+                continue;
+            }
+            // The poured block was the whole top of the sending tube, so it can't have
+            // been sitting on more of the same color, unless only part of it fit
+            let partial = send_tube.topcolor().1 == color;
+            if partial && (rules != Rules::PartialPour || recv_empty != 0) {
+                continue;
+            }
+            let send_empty = send_tube.howempty();
+            for amount in 1..=run.min(send_empty) {
+                // Taking the whole run leaves whatever was below it on top of the
+                // receiving tube, which could only have been poured onto if it was empty
+                if amount == run && run != recv_height {
+                    continue;
+                }
+                let mut previous_state = state.clone();
+                let recv = &mut previous_state.tubes[ri].layers;
+                for i in recv_empty..recv_empty + amount {
+                    recv[i as usize] = LiquidColor::Empty;
+                }
+                let send = &mut previous_state.tubes[si].layers;
+                for i in send_empty - amount..send_empty {
+                    send[i as usize] = color.clone();
+                }
+                previous_states.push((
+                    TransferAction {
+                        send_idx: si as i32,
+                        recv_idx: ri as i32,
+                    },
+                    previous_state,
+                ));
+            }
+        }
+    }
+    return previous_states;
+}
+
+/// Returns `mapping` such that tube `i` of `from` is the same as tube `mapping[i]` of `to`
+fn tube_mapping(from: &TubeState, to: &TubeState) -> Vec<usize> {
+    let mut from_order: Vec<usize> = (0..from.tubes.len()).collect();
+    from_order.sort_by(|a, b| from.tubes[*a].cmp(&from.tubes[*b]));
+    let mut to_order: Vec<usize> = (0..to.tubes.len()).collect();
+    to_order.sort_by(|a, b| to.tubes[*a].cmp(&to.tubes[*b]));
+    let mut mapping = vec![0; from.tubes.len()];
+    for (f, t) in from_order.into_iter().zip(to_order) {
+        mapping[f] = t;
+    }
+    return mapping;
+}
+
+/// Bidirectional breadth first search
+///
+/// Searches forward from the starting state with `neighbors` and backward from the
+/// distinct solved states with `reverse_neighbors`, growing one side by a full layer at a
+/// time. Pouring back out of solved tubes can be done in many more ways than pouring
+/// forward, so the side that is grown is the one whose next layer looks cheapest: its
+/// frontier times the number of states each of its expansions has generated so far. When
+/// the two searches reach the same state the moves of both halves are joined into one
+/// solution, which still has the fewest transfers.
+///
+/// Moves are pruned on both sides like `pruned_neighbors` does, unless the config turns
+/// pruning off. How much this saves over `Bfs` depends on the level: the forward side
+/// skips the last few layers before the solution, so levels whose layers are still wide
+/// that close to the end gain the most.
+///
+/// Like `Bfs`, both sides keep their states as `CompactState`s and the moves that reach
+/// them in a `MoveTree`.
+pub struct Bidirectional;
+
+impl Solver for Bidirectional {
+    fn name(&self) -> &str {
+        return "bidirectional";
+    }
+
+    fn solve(&self, initial_state: &TubeState, config: &SearchConfig) -> SearchReport {
+        let mut search = SearchContext::new(config);
+        let result = bidirectional(initial_state, &mut search);
+        return search.finish(result);
+    }
+}
+
+/// One half of a bidirectional search
+///
+/// `seen` maps the canonical form of every state reached to the real state and its node
+/// in `tree`, whose moves connect it to one of this side's roots: from the starting state
+/// for the forward side, and on to a solved state for the backward side.
+struct Side {
+    seen: HashMap<CompactState, CompactNode>,
+    tree: MoveTree,
+    frontier: Vec<CompactState>,
+    depth: usize,
+    expanded: usize,
+    generated: usize,
+}
+
+impl Side {
+    fn new(roots: &[TubeState], layout: &CompactLayout) -> Side {
+        let mut seen = HashMap::new();
+        let mut frontier = Vec::new();
+        for root in roots {
+            let state = layout.encode(root);
+            let canonical = layout.canonical(&state);
+            if seen.contains_key(&canonical) {
+                continue;
+            }
+            seen.insert(canonical.clone(), CompactNode::root(state));
+            frontier.push(canonical);
+        }
+        return Side {
+            seen,
+            tree: MoveTree::new(),
+            frontier,
+            depth: 0,
+            expanded: 0,
+            generated: 0,
+        };
+    }
+
+    /// Roughly how many states expanding the whole frontier would generate
+    fn next_layer_cost(&self) -> usize {
+        if self.expanded == 0 {
+            return self.frontier.len();
+        }
+        return self.frontier.len() * self.generated.div_ceil(self.expanded);
+    }
+
+    /// Returns the state this side reached with the given canonical form and its moves
+    fn path(&self, canonical: &CompactState, layout: &CompactLayout) -> TubeStateNode {
+        let node = &self.seen[canonical];
+        return TubeStateNode {
+            actions: self.tree.actions(node.node),
+            state: layout.decode(&node.state),
+        };
+    }
+}
+
+fn bidirectional(
+    initial_state: &TubeState,
+    search: &mut SearchContext,
+) -> Result<TubeStateNode, SolveError> {
+    initial_state.validate()?;
+    let rules = search.config().rules;
+    let prune_moves = search.config().prune_moves;
+    let layout = CompactLayout::new(initial_state);
+    let mut forward = Side::new(std::slice::from_ref(initial_state), &layout);
+    let mut backward = Side::new(&initial_state.solved_states(), &layout);
+    let initial_key = layout.canonical(&layout.encode(initial_state));
+    if backward.seen.contains_key(&initial_key) {
+        return join(
+            &forward.path(&initial_key, &layout),
+            &backward.path(&initial_key, &layout),
+            rules,
+        );
+    }
+    while !forward.frontier.is_empty() && !backward.frontier.is_empty() {
+        let is_forward = forward.next_layer_cost() <= backward.next_layer_cost();
+        let (side, other) = if is_forward {
+            (&mut forward, &backward)
+        } else {
+            (&mut backward, &forward)
+        };
+        let mut best: Option<(CompactState, usize)> = None;
+        let mut next_frontier = Vec::new();
+        for key in std::mem::take(&mut side.frontier) {
+            search.expand(side.depth)?;
+            side.expanded += 1;
+            let (parent, depth, packed) = {
+                let node = &side.seen[&key];
+                (node.node, node.depth, node.state.clone())
+            };
+            let previous = side.tree.last_move(parent);
+            let steps = if is_forward {
+                layout.successors(&packed, previous.as_ref(), search.config())
+            } else {
+                // The moves are checked from the state they are made in, which here is the
+                // state being generated
+                reverse_neighbors(&layout.decode(&packed), rules)
+                    .into_iter()
+                    .filter(|(action, previous_state)| {
+                        !prune_moves || !is_pruned(previous_state, action, previous.as_ref())
+                    })
+                    .map(|(action, previous_state)| (action, layout.encode(&previous_state)))
+                    .collect()
+            };
+            for (action, state) in steps {
+                search.generated();
+                side.generated += 1;
+                let canonical = layout.canonical(&state);
+                if side.seen.contains_key(&canonical) {
+                    search.duplicate();
+                    continue;
+                }
+                if let Some(meeting) = other.seen.get(&canonical) {
+                    let length = (depth + 1 + meeting.depth) as usize;
+                    if best
+                        .as_ref()
+                        .map_or(true, |(_, best_length)| length < *best_length)
+                    {
+                        best = Some((canonical.clone(), length));
+                    }
+                }
+                let node = CompactNode {
+                    node: side.tree.push(parent, &action),
+                    depth: depth + 1,
+                    state,
+                };
+                side.seen.insert(canonical.clone(), node);
+                next_frontier.push(canonical);
+            }
+        }
+        side.frontier = next_frontier;
+        side.depth += 1;
+        search.frontier(forward.frontier.len() + backward.frontier.len());
+        if let Some((meeting, _)) = best {
+            return join(
+                &forward.path(&meeting, &layout),
+                &backward.path(&meeting, &layout),
+                rules,
+            );
+        }
+    }
+    return Err(SolveError::Unsolvable);
+}
+
+/// Joins the forward path to a state with the backward path from the same state
+///
+/// The backward actions are stored from the solved state's side, so they are in reverse
+/// order and use the tube order of the backward search. They are renumbered to the tube
+/// order of the state the forward search reached.
+fn join(
+    forward: &TubeStateNode,
+    backward: &TubeStateNode,
+    rules: Rules,
+) -> Result<TubeStateNode, SolveError> {
+    let mapping = tube_mapping(&backward.state, &forward.state);
+    let mut actions = forward.actions.clone();
+    for action in backward.actions.iter().rev() {
+        actions.push(TransferAction {
+            send_idx: mapping[action.send_idx as usize] as i32,
+            recv_idx: mapping[action.recv_idx as usize] as i32,
+        });
+    }
+    let mut state = forward.state.clone();
+    for action in &actions[forward.actions.len()..] {
+        state = state.apply_with_rules(action, rules).map_err(|e| {
+            SolveError::InvalidInput(format!(
+                "the backward half of the solution can't make move {}: {}",
+                action, e
+            ))
+        })?;
+    }
+    return Ok(TubeStateNode { actions, state });
+}
+
+#[cfg(test)]
+mod bidirectional_tests {
+    use super::*;
+    use crate::{load_level, neighbors_with_rules, Bfs};
+
+    #[test]
+    fn test_reverse_neighbors_undo_neighbors() {
+        for rules in Rules::ALL {
+            let state = load_level("levels/7.toml");
+            let mut states = vec![state];
+            for _ in 0..3 {
+                let mut next_states = Vec::new();
+                for state in &states {
+                    for (action, next_state) in neighbors_with_rules(state, rules) {
+                        let previous_states = reverse_neighbors(&next_state, rules);
+                        assert!(previous_states.contains(&(action, state.clone())));
+                        next_states.push(next_state);
+                    }
+                }
+                states = next_states;
+            }
+        }
+    }
+
+    #[test]
+    fn test_reverse_neighbors_are_legal_moves() {
+        for rules in Rules::ALL {
+            let mut states = load_level("levels/7.toml").solved_states();
+            for _ in 0..2 {
+                let mut previous_states = Vec::new();
+                for state in &states {
+                    for (action, previous_state) in reverse_neighbors(state, rules) {
+                        let next_states = neighbors_with_rules(&previous_state, rules);
+                        assert!(next_states.contains(&(action, state.clone())));
+                        previous_states.push(previous_state);
+                    }
+                }
+                states = previous_states;
+            }
+        }
+    }
+
+    #[test]
+    fn test_bidirectional_expands_fewer_states_than_bfs() {
+        let pruned = SearchConfig::default();
+        let unpruned = SearchConfig {
+            prune_moves: false,
+            ..SearchConfig::default()
+        };
+        for level in [6, 8] {
+            let state = load_level(&format!("levels/{}.toml", level));
+            let expected = Bfs.solve(&state, &unpruned).result.unwrap().actions.len();
+            for config in [&pruned, &unpruned] {
+                let solution = Bidirectional.solve(&state, config).result.unwrap();
+                assert!(solution.state.issolved());
+                assert_eq!(solution.actions.len(), expected, "level {}", level);
+            }
+        }
+        // Meeting in the middle skips the last layers of the forward search, which hold a
+        // good share of the states on these levels
+        for path in ["levels/6.toml", "levels/capacity-6.toml"] {
+            let state = load_level(path);
+            let bfs_expanded = Bfs.solve(&state, &pruned).stats.nodes_expanded;
+            let expanded = Bidirectional.solve(&state, &pruned).stats.nodes_expanded;
+            assert!(expanded * 10 <= bfs_expanded * 9, "{}", path);
+        }
+    }
+}
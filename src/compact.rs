@@ -0,0 +1,529 @@
+use crate::{LiquidColor, Rules, SearchConfig, TransferAction, Tube, TubeState};
+
+/// A state packed into four bits per layer of liquid
+///
+/// The layers of every tube are stored one after the other from the top down, the same
+/// order as `Tube::layers`. Zero is an empty layer and any other value is a color of the
+/// `CompactLayout` the state was encoded with, which also knows where each tube starts.
+/// Two layers share each byte, unless the level has more than 15 colors and each layer
+/// needs a byte of its own. Storing a state this way takes a single small allocation
+/// instead of one per tube.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub struct CompactState {
+    packed: Box<[u8]>,
+}
+
+impl CompactState {
+    /// Returns roughly how many bytes the state takes up, counting its allocation
+    pub fn memory_size(&self) -> usize {
+        return std::mem::size_of::<CompactState>() + self.packed.len();
+    }
+
+    /// Returns the packed layers, which sort in the same order as the states
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        return &self.packed;
+    }
+
+    /// Creates a state from bytes that `as_bytes` returned
+    pub(crate) fn from_bytes(packed: Box<[u8]>) -> CompactState {
+        return CompactState { packed };
+    }
+}
+
+/// The most colors that still fit two layers into a byte
+const MAX_NARROW_COLORS: usize = 15;
+
+/// A packed state waiting to be expanded, the compact form of `TubeStateNode`
+///
+/// Instead of every move that led to it, the node keeps its place in a `MoveTree`.
+pub(crate) struct CompactNode {
+    pub node: u32,
+    pub depth: u32,
+    pub state: CompactState,
+}
+
+impl CompactNode {
+    /// Returns the node for the starting state, which is the root of a new `MoveTree`
+    pub fn root(state: CompactState) -> CompactNode {
+        return CompactNode {
+            node: MoveTree::ROOT,
+            depth: 0,
+            state,
+        };
+    }
+}
+
+/// The last move that reached a node and the node it was made from
+struct MoveTreeEntry {
+    parent: u32,
+    send_idx: u16,
+    recv_idx: u16,
+}
+
+/// The moves of every node a search has queued
+///
+/// Each node only stores its last move and where it came from, so queuing a state costs
+/// a few bytes however deep it is, and the moves that reach it are read back by walking
+/// up the tree.
+pub(crate) struct MoveTree {
+    entries: Vec<MoveTreeEntry>,
+}
+
+impl MoveTree {
+    /// The node of the starting state, which wasn't reached by a move
+    pub const ROOT: u32 = 0;
+
+    pub fn new() -> MoveTree {
+        let root = MoveTreeEntry {
+            parent: MoveTree::ROOT,
+            send_idx: 0,
+            recv_idx: 0,
+        };
+        return MoveTree {
+            entries: vec![root],
+        };
+    }
+
+    /// Adds the node reached by making `action` from `parent` and returns it
+    ///
+    /// Panics if there are more nodes than fit in a `u32` or tubes than fit in a `u16`.
+    pub fn push(&mut self, parent: u32, action: &TransferAction) -> u32 {
+        let node = u32::try_from(self.entries.len()).expect("too many states to queue");
+        self.entries.push(MoveTreeEntry {
+            parent,
+            send_idx: u16::try_from(action.send_idx()).expect("too many tubes"),
+            recv_idx: u16::try_from(action.recv_idx()).expect("too many tubes"),
+        });
+        return node;
+    }
+
+    /// Returns the move that reached the node, or `None` for the root
+    pub fn last_move(&self, node: u32) -> Option<TransferAction> {
+        if node == MoveTree::ROOT {
+            return None;
+        }
+        let entry = &self.entries[node as usize];
+        return Some(TransferAction::new(
+            entry.send_idx as usize,
+            entry.recv_idx as usize,
+        ));
+    }
+
+    /// Returns every move from the root to the node
+    pub fn actions(&self, node: u32) -> Vec<TransferAction> {
+        let mut actions = Vec::new();
+        let mut current = node;
+        while let Some(action) = self.last_move(current) {
+            actions.push(action);
+            current = self.entries[current as usize].parent;
+        }
+        actions.reverse();
+        return actions;
+    }
+}
+
+/// The colors and tube sizes of one level, which `CompactState`s are encoded against
+///
+/// ```
+/// use tubes::{CompactLayout, LiquidColor, Tube, TubeState};
+/// let state = TubeState {
+///     tubes: vec![
+///         Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Blue]),
+///         Tube::from_vec(vec![LiquidColor::Blue, LiquidColor::Orange]),
+///         Tube::from_vec(vec![]),
+///     ],
+/// };
+/// let layout = CompactLayout::new(&state);
+/// let compact = layout.encode(&state);
+/// assert_eq!(layout.decode(&compact), state);
+/// assert!(compact.memory_size() * 8 < TubeState::memory_size(&state));
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CompactLayout {
+    /// The color each value stands for, where value `i` is `colors[i - 1]`
+    colors: Vec<LiquidColor>,
+    /// Where the layers of each tube start, with one more entry for the end of the last
+    offsets: Vec<usize>,
+    /// Whether each layer takes a whole byte because there are too many colors for four
+    /// bits
+    wide: bool,
+}
+
+impl CompactLayout {
+    /// Creates a layout with the tube sizes of the state and every color in it
+    ///
+    /// Panics if the state has more than 255 colors, which no level comes close to.
+    pub fn new(state: &TubeState) -> CompactLayout {
+        let mut colors: Vec<LiquidColor> = Vec::new();
+        let mut offsets = vec![0];
+        for tube in &state.tubes {
+            for layer in &tube.layers {
+                if layer != &LiquidColor::Empty && !colors.contains(layer) {
+                    colors.push(layer.clone());
+                }
+            }
+            offsets.push(offsets.last().unwrap() + tube.capacity());
+        }
+        assert!(
+            colors.len() <= u8::MAX as usize,
+            "too many colors to encode"
+        );
+        let wide = colors.len() > MAX_NARROW_COLORS;
+        return CompactLayout {
+            colors,
+            offsets,
+            wide,
+        };
+    }
+
+    /// Packs the state, which has to have the same tube sizes and colors as the layout
+    pub fn encode(&self, state: &TubeState) -> CompactState {
+        let mut layers = Vec::with_capacity(*self.offsets.last().unwrap());
+        for tube in &state.tubes {
+            for layer in &tube.layers {
+                if layer == &LiquidColor::Empty {
+                    layers.push(0);
+                } else {
+                    let idx = self.colors.iter().position(|c| c == layer).unwrap();
+                    layers.push(idx as u8 + 1);
+                }
+            }
+        }
+        return self.pack(&layers);
+    }
+
+    /// Unpacks a state encoded with this layout
+    pub fn decode(&self, state: &CompactState) -> TubeState {
+        let unpacked = self.unpack(state);
+        let tubes = self
+            .offsets
+            .windows(2)
+            .map(|bounds| {
+                let layers = unpacked[bounds[0]..bounds[1]]
+                    .iter()
+                    .map(|&b| match b {
+                        0 => LiquidColor::Empty,
+                        b => self.colors[b as usize - 1].clone(),
+                    })
+                    .collect();
+                return Tube { layers };
+            })
+            .collect();
+        return TubeState { tubes };
+    }
+
+    /// Packs one value per layer into a state
+    fn pack(&self, layers: &[u8]) -> CompactState {
+        if self.wide {
+            return CompactState {
+                packed: layers.into(),
+            };
+        }
+        let packed = layers
+            .chunks(2)
+            .map(|pair| pair[0] << 4 | pair.get(1).unwrap_or(&0))
+            .collect();
+        return CompactState { packed };
+    }
+
+    /// Returns one value per layer of a packed state
+    fn unpack(&self, state: &CompactState) -> Vec<u8> {
+        if self.wide {
+            return state.packed.to_vec();
+        }
+        let mut layers: Vec<u8> = state
+            .packed
+            .iter()
+            .flat_map(|&byte| [byte >> 4, byte & 0xf])
+            .collect();
+        layers.truncate(*self.offsets.last().unwrap());
+        return layers;
+    }
+
+    /// Returns how many bytes every state packed with this layout takes
+    pub(crate) fn packed_len(&self) -> usize {
+        let layer_count = *self.offsets.last().unwrap();
+        if self.wide {
+            return layer_count;
+        }
+        return layer_count.div_ceil(2);
+    }
+
+    fn tube<'a>(&self, layers: &'a [u8], ti: usize) -> &'a [u8] {
+        return &layers[self.offsets[ti]..self.offsets[ti + 1]];
+    }
+
+    fn tube_count(&self) -> usize {
+        return self.offsets.len() - 1;
+    }
+
+    /// Returns true if every tube is empty or full of one color
+    pub fn issolved(&self, state: &CompactState) -> bool {
+        let layers = self.unpack(state);
+        return (0..self.tube_count()).all(|ti| {
+            let tube = self.tube(&layers, ti);
+            let (empty_count, color_count, _) = top_block(tube);
+            return empty_count == tube.len() || color_count == tube.len();
+        });
+    }
+
+    /// Returns the state with the tubes of each size sorted, like `TubeState::canonical`
+    ///
+    /// Tubes only trade places with tubes of the same size, since the layout fixes how
+    /// many layers each position holds.
+    pub fn canonical(&self, state: &CompactState) -> CompactState {
+        let layers = self.unpack(state);
+        let mut tubes: Vec<&[u8]> = (0..self.tube_count())
+            .map(|ti| self.tube(&layers, ti))
+            .collect();
+        let mut groups: Vec<(usize, Vec<usize>)> = Vec::new();
+        for (ti, tube) in tubes.iter().enumerate() {
+            match groups.iter_mut().find(|(size, _)| *size == tube.len()) {
+                Some((_, members)) => members.push(ti),
+                None => groups.push((tube.len(), vec![ti])),
+            }
+        }
+        for (_, members) in &groups {
+            let mut sorted: Vec<&[u8]> = members.iter().map(|&ti| tubes[ti]).collect();
+            sorted.sort();
+            for (&ti, tube) in members.iter().zip(sorted) {
+                tubes[ti] = tube;
+            }
+        }
+        return self.pack(&tubes.concat());
+    }
+
+    /// Returns the moves the search should try from `state` and where they lead, the
+    /// same moves in the same order as `SearchConfig::successors` on the decoded state
+    pub fn successors(
+        &self,
+        state: &CompactState,
+        previous: Option<&TransferAction>,
+        config: &SearchConfig,
+    ) -> Vec<(TransferAction, CompactState)> {
+        let layers = self.unpack(state);
+        let mut neighboring_states = Vec::new();
+        for si in 0..self.tube_count() {
+            let send_tube = self.tube(&layers, si);
+            let (send_empty, send_count, send_color) = top_block(send_tube);
+            if send_empty == send_tube.len() {
+                continue;
+            }
+            for ri in 0..self.tube_count() {
+                if si == ri {
+                    continue;
+                }
+                let recv_tube = self.tube(&layers, ri);
+                let (room, _, recv_color) = top_block(recv_tube);
+                if recv_color != 0 && recv_color != send_color {
+                    continue;
+                }
+                let poured_count = match config.rules {
+                    Rules::WholeBlock => send_count,
+                    Rules::PartialPour => send_count.min(room),
+                };
+                if poured_count == 0 || poured_count > room {
+                    continue;
+                }
+                let action = TransferAction::new(si, ri);
+                if config.prune_moves && !self.keep_move(&layers, &action, previous) {
+                    continue;
+                }
+                let mut next_layers = layers.clone();
+                let send_start = self.offsets[si] + send_empty;
+                next_layers[send_start..send_start + poured_count].fill(0);
+                let recv_end = self.offsets[ri] + room;
+                next_layers[recv_end - poured_count..recv_end].fill(send_color);
+                neighboring_states.push((action, self.pack(&next_layers)));
+            }
+        }
+        return neighboring_states;
+    }
+
+    /// Returns false for the legal moves `pruned_neighbors` leaves out
+    fn keep_move(
+        &self,
+        layers: &[u8],
+        action: &TransferAction,
+        previous: Option<&TransferAction>,
+    ) -> bool {
+        let send_tube = self.tube(layers, action.send_idx());
+        let recv_tube = self.tube(layers, action.recv_idx());
+        let is_empty = |tube: &[u8]| tube.iter().all(|&b| b == 0);
+        if is_empty(recv_tube) {
+            let (send_empty, send_count, _) = top_block(send_tube);
+            if send_empty + send_count == send_tube.len() && send_tube.len() == recv_tube.len() {
+                return false;
+            }
+            let first_empty = (0..self.tube_count()).find(|&ti| {
+                let tube = self.tube(layers, ti);
+                return is_empty(tube) && tube.len() == recv_tube.len();
+            });
+            if first_empty != Some(action.recv_idx()) {
+                return false;
+            }
+        }
+        if let Some(previous) = previous {
+            if previous.send_idx() == action.recv_idx() && previous.recv_idx() == action.send_idx()
+            {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+
+/// Returns how many empty layers an unpacked tube has, and the size and color of the block
+/// of liquid under them
+fn top_block(tube: &[u8]) -> (usize, usize, u8) {
+    let empty_count = tube.iter().take_while(|&&b| b == 0).count();
+    let color = match tube.get(empty_count) {
+        Some(&color) => color,
+        None => return (empty_count, 0, 0),
+    };
+    let color_count = tube[empty_count..]
+        .iter()
+        .take_while(|&&b| b == color)
+        .count();
+    return (empty_count, color_count, color);
+}
+
+impl TubeState {
+    /// Returns roughly how many bytes the state takes up, counting its allocations
+    pub fn memory_size(&self) -> usize {
+        let layer_count: usize = self.tubes.iter().map(|t| t.capacity()).sum();
+        return std::mem::size_of::<TubeState>()
+            + self.tubes.len() * std::mem::size_of::<Tube>()
+            + layer_count * std::mem::size_of::<LiquidColor>();
+    }
+}
+
+#[cfg(test)]
+mod compact_tests {
+    use super::*;
+    use crate::{load_level, ColorId, Solver};
+
+    fn levels() -> Vec<TubeState> {
+        let mut paths: Vec<_> = std::fs::read_dir("levels")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+        return paths
+            .iter()
+            .map(|path| load_level(path.to_str().unwrap()))
+            .filter(|state| state.hidden_count() == 0)
+            .collect();
+    }
+
+    #[test]
+    fn test_successors_match_search_config() {
+        for state in levels() {
+            let layout = CompactLayout::new(&state);
+            for rules in Rules::ALL {
+                for prune_moves in [true, false] {
+                    let config = SearchConfig {
+                        prune_moves,
+                        rules,
+                        ..SearchConfig::default()
+                    };
+                    // Walk a few moves in so the tubes aren't all full or empty
+                    let mut current = state.clone();
+                    let mut previous = None;
+                    for _ in 0..4 {
+                        let expected = config.successors(&current, previous.as_ref());
+                        let actual: Vec<(TransferAction, TubeState)> = layout
+                            .successors(&layout.encode(&current), previous.as_ref(), &config)
+                            .into_iter()
+                            .map(|(action, next)| (action, layout.decode(&next)))
+                            .collect();
+                        assert_eq!(actual, expected);
+                        match expected.last() {
+                            Some((action, next)) => {
+                                previous = Some(*action);
+                                current = next.clone();
+                            }
+                            None => break,
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_canonical_matches_tube_state() {
+        for state in levels() {
+            let layout = CompactLayout::new(&state);
+            let mut reversed = state.clone();
+            reversed.tubes.reverse();
+            let same_sizes = reversed
+                .tubes
+                .iter()
+                .zip(&state.tubes)
+                .all(|(a, b)| a.capacity() == b.capacity());
+            let compact = layout.encode(&state);
+            assert_eq!(layout.issolved(&compact), state.issolved());
+            if same_sizes {
+                assert_eq!(
+                    layout.canonical(&compact),
+                    layout.canonical(&layout.encode(&reversed))
+                );
+            }
+            assert_eq!(
+                layout.decode(&layout.canonical(&compact)).canonical(),
+                state.canonical()
+            );
+        }
+    }
+
+    #[test]
+    fn test_many_colors_take_a_byte_per_layer() {
+        let mut tubes: Vec<Tube> = (0..20u8)
+            .map(|i| {
+                let color = LiquidColor::Other(ColorId(i as u32));
+                return Tube::from_vec_with_capacity(vec![color.clone(), color], 3);
+            })
+            .collect();
+        tubes.push(Tube::empty(3));
+        let state = TubeState { tubes };
+        let layout = CompactLayout::new(&state);
+        assert!(layout.wide);
+        let compact = layout.encode(&state);
+        assert_eq!(layout.decode(&compact), state);
+        let config = SearchConfig::default();
+        for (action, next) in layout.successors(&compact, None, &config) {
+            assert_eq!(Some(layout.decode(&next)), state.apply(&action).ok());
+        }
+    }
+
+    #[test]
+    fn test_compact_states_are_much_smaller() {
+        let state = load_level("levels/8.toml");
+        let layout = CompactLayout::new(&state);
+        assert!(layout.encode(&state).memory_size() * 10 <= state.memory_size());
+        // A queued node stays small however many moves it took to reach
+        let solution = crate::Bfs
+            .solve(&state, &SearchConfig::default())
+            .result
+            .unwrap();
+        let mut tree = MoveTree::new();
+        let mut node = CompactNode::root(layout.encode(&state));
+        for action in &solution.actions {
+            node = CompactNode {
+                node: tree.push(node.node, action),
+                depth: node.depth + 1,
+                state: node.state,
+            };
+        }
+        assert_eq!(tree.actions(node.node), solution.actions);
+        let full_node_size = std::mem::size_of::<crate::TubeStateNode>()
+            + state.memory_size()
+            + solution.actions.len() * std::mem::size_of::<TransferAction>();
+        // The node itself, its packed layers and its entry in the tree
+        let node_size = std::mem::size_of::<CompactNode>() - std::mem::size_of::<CompactState>()
+            + node.state.memory_size()
+            + std::mem::size_of::<MoveTreeEntry>();
+        assert!(node_size * 10 <= full_node_size);
+    }
+}
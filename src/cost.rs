@@ -0,0 +1,209 @@
+use crate::{lower_bound, Rules, TransferAction, TubeState};
+use std::fmt;
+use std::str::FromStr;
+
+/// How much extra a pour into an empty tube costs under `CostModel::EmptyTubes`
+///
+/// This is larger than the number of pours in any real solution, so a single pour into an
+/// empty tube always costs more than any number of other pours.
+pub const EMPTY_TUBE_PENALTY: u64 = 1 << 20;
+
+/// What a weighted search tries to minimize
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CostModel {
+    /// The number of transfers, every pour costs one
+    Pours,
+    /// The total layers of liquid moved by all of the pours
+    Units,
+    /// The number of pours into empty tubes, then the number of pours
+    EmptyTubes,
+}
+
+impl CostModel {
+    pub const ALL: [CostModel; 3] = [CostModel::Pours, CostModel::Units, CostModel::EmptyTubes];
+
+    pub fn name(&self) -> &str {
+        match self {
+            CostModel::Pours => return "pours",
+            CostModel::Units => return "units",
+            CostModel::EmptyTubes => return "empty-tubes",
+        }
+    }
+
+    /// Returns the cost of taking `action` from `state`, which leads to `next_state`
+    ///
+    /// Every move costs at least one, which keeps `lower_bound` admissible for all models.
+    pub fn cost(&self, state: &TubeState, action: &TransferAction, next_state: &TubeState) -> u64 {
+        let send_idx = action.send_idx as usize;
+        let recv_idx = action.recv_idx as usize;
+        match self {
+            CostModel::Pours => return 1,
+            CostModel::Units => {
+                let moved =
+                    next_state.tubes[send_idx].howempty() - state.tubes[send_idx].howempty();
+                return moved as u64;
+            }
+            CostModel::EmptyTubes => {
+                if state.tubes[recv_idx].isempty() {
+                    return 1 + EMPTY_TUBE_PENALTY;
+                }
+                return 1;
+            }
+        }
+    }
+
+    /// Returns a lower bound on the cost of solving the state
+    ///
+    /// Every move costs at least one and solving takes at least `lower_bound` moves. The
+    /// bound drops by at most one per move, so it is consistent as well as admissible. For
+    /// `Units` and `EmptyTubes` it is loose, since a move often costs more than one;
+    /// `UniformCost` searches without it, as a check that `WeightedAStar` finds the same cost.
+    pub fn lower_bound(&self, state: &TubeState) -> u64 {
+        return lower_bound(state) as u64;
+    }
+
+    /// Describes a total cost in words
+    ///
+    /// ```
+    /// use tubes::{CostModel, EMPTY_TUBE_PENALTY};
+    /// assert_eq!(CostModel::Units.describe(12), "12 units moved");
+    /// assert_eq!(
+    ///     CostModel::EmptyTubes.describe(2 * EMPTY_TUBE_PENALTY + 9),
+    ///     "2 pours into empty tubes out of 9 pours"
+    /// );
+    /// ```
+    pub fn describe(&self, cost: u64) -> String {
+        match self {
+            CostModel::Pours => return format!("{} pours", cost),
+            CostModel::Units => return format!("{} units moved", cost),
+            CostModel::EmptyTubes => {
+                return format!(
+                    "{} pours into empty tubes out of {} pours",
+                    cost / EMPTY_TUBE_PENALTY,
+                    cost % EMPTY_TUBE_PENALTY
+                );
+            }
+        }
+    }
+
+    /// Returns the total cost of playing `actions` from `initial_state` under `rules`, or
+    /// `None` if one of the actions isn't a legal move
+    ///
+    /// ```
+    /// use tubes::{CostModel, LiquidColor, Rules, Tube, TubeState};
+    /// let state = TubeState {
+    ///     tubes: vec![
+    ///         Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Orange]),
+    ///         Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Orange]),
+    ///     ],
+    /// };
+    /// let solution = tubes::solve_bfs(&state).unwrap();
+    /// let rules = Rules::WholeBlock;
+    /// assert_eq!(CostModel::Pours.solution_cost(&state, &solution.actions, rules), Some(1));
+    /// assert_eq!(CostModel::Units.solution_cost(&state, &solution.actions, rules), Some(2));
+    /// ```
+    pub fn solution_cost(
+        &self,
+        initial_state: &TubeState,
+        actions: &[TransferAction],
+        rules: Rules,
+    ) -> Option<u64> {
+        let mut state = initial_state.clone();
+        let mut total = 0;
+        for action in actions {
+            let next_state = state.apply_with_rules(action, rules).ok()?;
+            total += self.cost(&state, action, &next_state);
+            state = next_state;
+        }
+        return Some(total);
+    }
+}
+
+impl fmt::Display for CostModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for CostModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        for cost_model in CostModel::ALL {
+            if cost_model.name() == s {
+                return Ok(cost_model);
+            }
+        }
+        let names: Vec<&str> = CostModel::ALL.iter().map(|c| c.name()).collect();
+        return Err(format!(
+            "unknown cost model {}, expected one of: {}",
+            s,
+            names.join(", ")
+        ));
+    }
+}
+
+#[cfg(test)]
+mod cost_tests {
+    use super::*;
+    use crate::{load_level, Bfs, SearchConfig, Solver, UniformCost, WeightedAStar};
+
+    #[test]
+    fn test_cost_model_names() {
+        for cost_model in CostModel::ALL {
+            assert_eq!(cost_model.name().parse(), Ok(cost_model));
+        }
+        assert!("liters".parse::<CostModel>().is_err());
+    }
+
+    #[test]
+    fn test_each_model_minimizes_its_own_cost() {
+        let config = SearchConfig::default();
+        for level in 2..=8 {
+            let state = load_level(&format!("levels/{}.toml", level));
+            let fewest_pours = Bfs.solve(&state, &config).result.unwrap().actions;
+            for cost_model in CostModel::ALL {
+                let solver = WeightedAStar { cost_model };
+                let solution = solver.solve(&state, &config).result.unwrap();
+                assert!(solution.state.issolved());
+                assert!(solution.actions.len() >= fewest_pours.len());
+                let cost = cost_model
+                    .solution_cost(&state, &solution.actions, Rules::WholeBlock)
+                    .unwrap();
+                let pours_cost = cost_model
+                    .solution_cost(&state, &fewest_pours, Rules::WholeBlock)
+                    .unwrap();
+                assert!(cost <= pours_cost, "{} on level {}", cost_model, level);
+                if cost_model == CostModel::Pours {
+                    assert_eq!(solution.actions.len(), fewest_pours.len());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_uniform_cost_matches_weighted_astar() {
+        let config = SearchConfig::default();
+        for level in 2..=6 {
+            let state = load_level(&format!("levels/{}.toml", level));
+            for cost_model in CostModel::ALL {
+                let astar = WeightedAStar { cost_model }.solve(&state, &config);
+                let uniform = UniformCost { cost_model }.solve(&state, &config);
+                let astar_cost = cost_model
+                    .solution_cost(&state, &astar.result.unwrap().actions, Rules::WholeBlock)
+                    .unwrap();
+                let solution = uniform.result.unwrap();
+                assert!(solution.state.issolved());
+                let uniform_cost = cost_model
+                    .solution_cost(&state, &solution.actions, Rules::WholeBlock)
+                    .unwrap();
+                assert_eq!(
+                    uniform_cost, astar_cost,
+                    "{} on level {}",
+                    cost_model, level
+                );
+                assert!(uniform.stats.nodes_expanded >= astar.stats.nodes_expanded);
+            }
+        }
+    }
+}
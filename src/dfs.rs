@@ -0,0 +1,83 @@
+use crate::compact::{CompactNode, MoveTree};
+use crate::search::SearchContext;
+use crate::{
+    CompactLayout, CompactState, SearchConfig, SearchReport, SolveError, Solver, TubeState,
+    TubeStateNode,
+};
+use std::collections::HashSet;
+
+/// Depth first search
+///
+/// Follows one line of play as deep as it goes before backtracking, so it often finds a
+/// solution after expanding very few states, but the solution is usually far from the
+/// shortest one. Like the other solvers each state is only visited once, so the search
+/// still ends with `SolveError::Unsolvable` when there is no solution.
+pub struct Dfs;
+
+impl Solver for Dfs {
+    fn name(&self) -> &str {
+        return "dfs";
+    }
+
+    fn solve(&self, initial_state: &TubeState, config: &SearchConfig) -> SearchReport {
+        let mut search = SearchContext::new(config);
+        let result = dfs(initial_state, &mut search);
+        return search.finish(result);
+    }
+}
+
+fn dfs(initial_state: &TubeState, search: &mut SearchContext) -> Result<TubeStateNode, SolveError> {
+    initial_state.validate()?;
+    let layout = CompactLayout::new(initial_state);
+    let mut tree = MoveTree::new();
+    let mut explored: HashSet<CompactState> = HashSet::new();
+    let mut stack: Vec<CompactNode> = Vec::new();
+    let root = CompactNode::root(layout.encode(initial_state));
+    explored.insert(layout.canonical(&root.state));
+    stack.push(root);
+    while let Some(node) = stack.pop() {
+        if layout.issolved(&node.state) {
+            return Ok(TubeStateNode {
+                actions: tree.actions(node.node),
+                state: layout.decode(&node.state),
+            });
+        }
+        search.expand(node.depth as usize)?;
+        let previous = tree.last_move(node.node);
+        // Push in reverse so the first neighbor is explored first
+        for (action, state) in layout
+            .successors(&node.state, previous.as_ref(), search.config())
+            .into_iter()
+            .rev()
+        {
+            search.generated();
+            if explored.insert(layout.canonical(&state)) {
+                stack.push(CompactNode {
+                    node: tree.push(node.node, &action),
+                    depth: node.depth + 1,
+                    state,
+                });
+            } else {
+                search.duplicate();
+            }
+        }
+        search.frontier(stack.len());
+    }
+    return Err(SolveError::Unsolvable);
+}
+
+#[cfg(test)]
+mod dfs_tests {
+    use super::*;
+    use crate::load_level;
+
+    #[test]
+    fn test_dfs_level_2() {
+        let state = load_level("levels/2.toml");
+        let report = Dfs.solve(&state, &SearchConfig::default());
+        let solution = report.result.unwrap();
+        assert!(solution.state.issolved());
+        assert!(solution.actions.len() >= 7);
+        assert!(report.stats.nodes_expanded > 0);
+    }
+}
@@ -0,0 +1,410 @@
+use crate::search::SearchContext;
+use crate::{neighbors_with_rules, Rules, SearchConfig, SolveError, TransferAction, TubeState};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+
+/// Every state reachable from a starting state and the moves between them
+///
+/// States that only differ by the order of their tubes share a node, keyed by
+/// `TubeState::canonical`. Each node keeps the first state found for it, and its edges are
+/// every move from that state, including ones `prune_moves` would skip, so two moves that
+/// reach equivalent states are both counted. Equivalent states have the same moves up to
+/// the order of the tubes, so the counts hold for every state of the node.
+struct StateGraph {
+    states: Vec<TubeState>,
+    index: HashMap<TubeState, usize>,
+    rules: Rules,
+    edges: Vec<Vec<(TransferAction, usize)>>,
+    /// The fewest moves from each state to a solved state, if there is a way
+    distance_to_goal: Vec<Option<usize>>,
+}
+
+impl StateGraph {
+    fn build(
+        initial_state: &TubeState,
+        search: &mut SearchContext,
+    ) -> Result<StateGraph, SolveError> {
+        initial_state.validate()?;
+        let rules = search.config().rules;
+        let mut states = vec![initial_state.clone()];
+        let mut index: HashMap<TubeState, usize> = HashMap::new();
+        index.insert(initial_state.canonical(), 0);
+        let mut edges = Vec::new();
+        let mut depth = vec![0];
+        let mut next = 0;
+        while next < states.len() {
+            let mut state_edges = Vec::new();
+            if !states[next].issolved() {
+                search.expand(depth[next])?;
+                for (action, state) in neighbors_with_rules(&states[next], rules) {
+                    search.generated();
+                    let key = state.canonical();
+                    let target = match index.get(&key) {
+                        Some(target) => {
+                            search.duplicate();
+                            *target
+                        }
+                        None => {
+                            index.insert(key, states.len());
+                            states.push(state);
+                            depth.push(depth[next] + 1);
+                            states.len() - 1
+                        }
+                    };
+                    state_edges.push((action, target));
+                }
+            }
+            edges.push(state_edges);
+            search.frontier(states.len() - next);
+            next += 1;
+        }
+        let mut reverse_edges = vec![Vec::new(); states.len()];
+        for (from, state_edges) in edges.iter().enumerate() {
+            for (_, to) in state_edges {
+                reverse_edges[*to].push(from);
+            }
+        }
+        let mut distance_to_goal = vec![None; states.len()];
+        let mut q = VecDeque::new();
+        for (i, state) in states.iter().enumerate() {
+            if state.issolved() {
+                distance_to_goal[i] = Some(0);
+                q.push_back(i);
+            }
+        }
+        while let Some(to) = q.pop_front() {
+            let distance = distance_to_goal[to].unwrap() + 1;
+            for from in &reverse_edges[to] {
+                if distance_to_goal[*from].is_none() {
+                    distance_to_goal[*from] = Some(distance);
+                    q.push_back(*from);
+                }
+            }
+        }
+        if distance_to_goal[0].is_none() {
+            return Err(SolveError::Unsolvable);
+        }
+        return Ok(StateGraph {
+            states,
+            index,
+            rules,
+            edges,
+            distance_to_goal,
+        });
+    }
+
+    /// Returns solutions in order of length, stopping after `max_solutions` or when the
+    /// next solution would be longer than `max_length`
+    ///
+    /// Partial solutions are expanded in order of their length plus the exact distance
+    /// left to a solved state, so every partial solution that is popped can be finished
+    /// at that length unless it would have to revisit one of its own states.
+    ///
+    /// The moves are made on the real states along each path rather than taken from the
+    /// edges, since a node's state may have its tubes in a different order.
+    fn solutions(&self, max_solutions: usize, max_length: usize) -> Vec<Vec<TransferAction>> {
+        let mut solutions = Vec::new();
+        let mut reached = vec![self.states[0].clone()];
+        let mut open = BinaryHeap::new();
+        open.push(Reverse((
+            self.distance_to_goal[0].unwrap(),
+            vec![0usize],
+            vec![],
+            0usize,
+        )));
+        while let Some(Reverse((estimate, path, actions, reached_idx))) = open.pop() {
+            if solutions.len() >= max_solutions || estimate > max_length {
+                break;
+            }
+            let current = *path.last().unwrap();
+            if self.states[current].issolved() {
+                solutions.push(actions);
+                continue;
+            }
+            for (action, state) in neighbors_with_rules(&reached[reached_idx], self.rules) {
+                let next = self.index[&state.canonical()];
+                let distance = match self.distance_to_goal[next] {
+                    Some(distance) => distance,
+                    None => continue,
+                };
+                if path.contains(&next) {
+                    continue;
+                }
+                let mut next_path = path.clone();
+                next_path.push(next);
+                let mut next_actions: Vec<TransferAction> = actions.clone();
+                next_actions.push(action);
+                reached.push(state);
+                open.push(Reverse((
+                    next_actions.len() + distance,
+                    next_path,
+                    next_actions,
+                    reached.len() - 1,
+                )));
+            }
+        }
+        return solutions;
+    }
+
+    /// Counts the solutions with the fewest moves without listing them
+    fn count_shortest(&self) -> u128 {
+        let mut order: Vec<usize> = (0..self.states.len())
+            .filter(|i| self.distance_to_goal[*i].is_some())
+            .collect();
+        order.sort_by_key(|i| self.distance_to_goal[*i]);
+        let mut counts = vec![0u128; self.states.len()];
+        for i in order {
+            let distance = self.distance_to_goal[i].unwrap();
+            if distance == 0 {
+                counts[i] = 1;
+                continue;
+            }
+            for (_, next) in &self.edges[i] {
+                if self.distance_to_goal[*next] == Some(distance - 1) {
+                    counts[i] = counts[i].saturating_add(counts[*next]);
+                }
+            }
+        }
+        return counts[0];
+    }
+}
+
+/// Returns every solution with the fewest moves, up to `max_solutions` of them
+///
+/// This has to look at every state reachable from the starting state, so the limits in
+/// the config apply to building that graph. Every move is considered whatever
+/// `prune_moves` is set to, so pours into either of two empty tubes are both listed.
+///
+/// ```
+/// use tubes::{shortest_solutions, LiquidColor, SearchConfig, Tube, TubeState};
+/// let state = TubeState {
+///     tubes: vec![
+///         Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Orange]),
+///         Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Orange]),
+///     ],
+/// };
+/// let solutions = shortest_solutions(&state, usize::MAX, &SearchConfig::default()).unwrap();
+/// // Either tube can be poured into the other
+/// assert_eq!(solutions.len(), 2);
+/// ```
+pub fn shortest_solutions(
+    initial_state: &TubeState,
+    max_solutions: usize,
+    config: &SearchConfig,
+) -> Result<Vec<Vec<TransferAction>>, SolveError> {
+    let mut search = SearchContext::new(config);
+    let graph = StateGraph::build(initial_state, &mut search)?;
+    let shortest = graph.distance_to_goal[0].unwrap();
+    return Ok(graph.solutions(max_solutions, shortest));
+}
+
+/// Returns the number of solutions with the fewest moves
+///
+/// The count saturates at `u128::MAX` instead of overflowing.
+pub fn count_shortest_solutions(
+    initial_state: &TubeState,
+    config: &SearchConfig,
+) -> Result<u128, SolveError> {
+    let mut search = SearchContext::new(config);
+    let graph = StateGraph::build(initial_state, &mut search)?;
+    return Ok(graph.count_shortest());
+}
+
+/// Returns the solutions `shortest_solutions` would and the number
+/// `count_shortest_solutions` would, building the graph of reachable states only once
+pub fn shortest_solutions_with_count(
+    initial_state: &TubeState,
+    max_solutions: usize,
+    config: &SearchConfig,
+) -> Result<(Vec<Vec<TransferAction>>, u128), SolveError> {
+    let mut search = SearchContext::new(config);
+    let graph = StateGraph::build(initial_state, &mut search)?;
+    let shortest = graph.distance_to_goal[0].unwrap();
+    return Ok((
+        graph.solutions(max_solutions, shortest),
+        graph.count_shortest(),
+    ));
+}
+
+/// Returns the `k` shortest distinct solutions in order of length
+///
+/// Solutions never visit the same state twice, so a solution can't be made longer just by
+/// pouring back and forth. Fewer than `k` solutions are returned if there aren't that many.
+pub fn k_shortest_solutions(
+    initial_state: &TubeState,
+    k: usize,
+    config: &SearchConfig,
+) -> Result<Vec<Vec<TransferAction>>, SolveError> {
+    let mut search = SearchContext::new(config);
+    let graph = StateGraph::build(initial_state, &mut search)?;
+    return Ok(graph.solutions(k, usize::MAX));
+}
+
+/// Returns true if the two moves touch different tubes, so they can be made in either order
+fn independent(a: &TransferAction, b: &TransferAction) -> bool {
+    let a_tubes = [a.send_idx, a.recv_idx];
+    return !a_tubes.contains(&b.send_idx) && !a_tubes.contains(&b.recv_idx);
+}
+
+/// Returns the representative of all of the orderings of `actions` that only differ by
+/// swapping neighboring moves that touch different tubes
+///
+/// At each step the smallest move that could be made next without changing the outcome
+/// is taken, so two solutions have the same representative exactly when one can be
+/// turned into the other by such swaps.
+fn commuting_normal_form(actions: &[TransferAction]) -> Vec<TransferAction> {
+    let mut remaining = actions.to_vec();
+    let mut normal_form = Vec::new();
+    while !remaining.is_empty() {
+        let mut best = 0;
+        for i in 1..remaining.len() {
+            let movable = remaining[..i].iter().all(|a| independent(a, &remaining[i]));
+            if movable && remaining[i] < remaining[best] {
+                best = i;
+            }
+        }
+        normal_form.push(remaining.remove(best));
+    }
+    return normal_form;
+}
+
+/// Groups solutions that only differ by the order of moves that touch different tubes
+///
+/// Groups are returned in the order their first solution appears in `solutions`.
+///
+/// ```
+/// use tubes::{group_commuting_solutions, shortest_solutions, LiquidColor, SearchConfig};
+/// use tubes::{Tube, TubeState};
+/// let state = TubeState {
+///     tubes: vec![
+///         Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Orange]),
+///         Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Orange]),
+///         Tube::from_vec(vec![LiquidColor::Blue, LiquidColor::Blue]),
+///         Tube::from_vec(vec![LiquidColor::Blue, LiquidColor::Blue]),
+///     ],
+/// };
+/// let solutions = shortest_solutions(&state, usize::MAX, &SearchConfig::default()).unwrap();
+/// // Two choices for each color, each in either order
+/// assert_eq!(solutions.len(), 8);
+/// // The order of the orange and blue pours doesn't matter
+/// assert_eq!(group_commuting_solutions(&solutions).len(), 4);
+/// ```
+pub fn group_commuting_solutions(
+    solutions: &[Vec<TransferAction>],
+) -> Vec<Vec<Vec<TransferAction>>> {
+    let mut groups: Vec<Vec<Vec<TransferAction>>> = Vec::new();
+    let mut group_index: HashMap<Vec<TransferAction>, usize> = HashMap::new();
+    for solution in solutions {
+        let normal_form = commuting_normal_form(solution);
+        match group_index.get(&normal_form) {
+            Some(i) => groups[*i].push(solution.clone()),
+            None => {
+                group_index.insert(normal_form, groups.len());
+                groups.push(vec![solution.clone()]);
+            }
+        }
+    }
+    return groups;
+}
+
+#[cfg(test)]
+mod enumerate_tests {
+    use super::*;
+    use crate::{load_level, replay, solve_bfs};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_all_shortest_solutions_level_3() {
+        let state = load_level("levels/3.toml");
+        let config = SearchConfig::default();
+        let shortest = solve_bfs(&state).unwrap().actions.len();
+        let solutions = shortest_solutions(&state, usize::MAX, &config).unwrap();
+        let count = count_shortest_solutions(&state, &config).unwrap();
+        assert_eq!(solutions.len() as u128, count);
+        let distinct: HashSet<_> = solutions.iter().collect();
+        assert_eq!(distinct.len(), solutions.len());
+        for solution in &solutions {
+            assert_eq!(solution.len(), shortest);
+            assert!(replay(&state, solution).unwrap().pop().unwrap().issolved());
+        }
+        let (listed, listed_count) = shortest_solutions_with_count(&state, 3, &config).unwrap();
+        assert_eq!(listed, solutions[..3]);
+        assert_eq!(listed_count, count);
+    }
+
+    #[test]
+    fn test_k_shortest_solutions_level_2() {
+        let state = load_level("levels/2.toml");
+        let config = SearchConfig::default();
+        let shortest = solve_bfs(&state).unwrap().actions.len();
+        let solutions = k_shortest_solutions(&state, 20, &config).unwrap();
+        assert_eq!(solutions.len(), 20);
+        assert_eq!(solutions[0].len(), shortest);
+        for pair in solutions.windows(2) {
+            assert!(pair[0].len() <= pair[1].len());
+        }
+        let distinct: HashSet<_> = solutions.iter().collect();
+        assert_eq!(distinct.len(), solutions.len());
+        for solution in &solutions {
+            assert!(replay(&state, solution).unwrap().pop().unwrap().issolved());
+        }
+    }
+
+    #[test]
+    fn test_groups_share_final_state() {
+        let state = load_level("levels/4.toml");
+        let solutions = shortest_solutions(&state, usize::MAX, &SearchConfig::default()).unwrap();
+        let groups = group_commuting_solutions(&solutions);
+        assert!(groups.len() <= solutions.len());
+        assert_eq!(
+            groups.iter().map(|g| g.len()).sum::<usize>(),
+            solutions.len()
+        );
+        for group in groups {
+            let final_state = replay(&state, &group[0]).unwrap().pop().unwrap();
+            for solution in &group[1..] {
+                assert_eq!(
+                    replay(&state, solution).unwrap().pop().unwrap(),
+                    final_state
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_pours_into_each_empty_tube_are_listed() {
+        let state = load_level("levels/3.toml");
+        let config = SearchConfig::default();
+        assert!(config.prune_moves);
+        let empty: Vec<i32> = (0..state.tubes.len())
+            .filter(|i| state.tubes[*i].isempty())
+            .map(|i| i as i32)
+            .collect();
+        assert_eq!(empty.len(), 2);
+        let swap = |idx: i32| {
+            if idx == empty[0] {
+                return empty[1];
+            }
+            if idx == empty[1] {
+                return empty[0];
+            }
+            return idx;
+        };
+        let solutions = shortest_solutions(&state, usize::MAX, &config).unwrap();
+        assert_eq!(
+            solutions.len() as u128,
+            count_shortest_solutions(&state, &config).unwrap()
+        );
+        assert!(solutions.iter().any(|s| s[0].recv_idx == empty[0]));
+        for solution in &solutions {
+            let swapped: Vec<TransferAction> = solution
+                .iter()
+                .map(|action| TransferAction {
+                    send_idx: swap(action.send_idx),
+                    recv_idx: swap(action.recv_idx),
+                })
+                .collect();
+            assert!(solutions.contains(&swapped), "{:?}", swapped);
+        }
+    }
+}
@@ -0,0 +1,412 @@
+use crate::compact::{CompactLayout, CompactState};
+use crate::search::SearchContext;
+use crate::{SearchConfig, SearchReport, SolveError, Solver, TubeState, TubeStateNode};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How many new states `ExternalBfs` keeps in memory by default before writing them out
+pub const DEFAULT_STATES_IN_MEMORY: usize = 1_000_000;
+
+/// Breadth first search that keeps its states in files instead of memory
+///
+/// Every layer of states the same number of transfers from the start is written to a
+/// sorted file in a temporary directory, next to a sorted file of every state visited so
+/// far. The states generated from a layer are collected in memory until there are
+/// `max_states_in_memory` of them, then sorted and written out as a run. Once the layer
+/// is done the runs are merged together, which drops duplicates between them, and
+/// against the visited file, which drops states found in earlier layers. Memory use
+/// doesn't grow with the number of states, so given enough disk `solve` can finish
+/// levels with billions of them.
+///
+/// The files hold whole states, packed and in their canonical form as `CompactState`s,
+/// rather than hashes of them, so two different states are never mistaken for the same
+/// one; with four bits per layer that is still only a few dozen bytes each.
+///
+/// Only the moves that `pruned_neighbors` skips without looking at the previous move are
+/// pruned, and the solution is put back together from the layer files once a solved
+/// state turns up, so it has the fewest transfers just like `Bfs`. The temporary
+/// directory is removed when the search ends.
+pub struct ExternalBfs {
+    /// Where to create the temporary directory, or the system temporary directory if
+    /// `None`
+    pub temp_dir: Option<PathBuf>,
+    /// How many new states are kept in memory before they're sorted and written out
+    pub max_states_in_memory: usize,
+}
+
+impl Default for ExternalBfs {
+    fn default() -> Self {
+        return ExternalBfs {
+            temp_dir: None,
+            max_states_in_memory: DEFAULT_STATES_IN_MEMORY,
+        };
+    }
+}
+
+impl Solver for ExternalBfs {
+    fn name(&self) -> &str {
+        return "external-bfs";
+    }
+
+    fn solve(&self, initial_state: &TubeState, config: &SearchConfig) -> SearchReport {
+        let mut search = SearchContext::new(config);
+        let result = external_bfs(initial_state, self, &mut search);
+        return search.finish(result);
+    }
+}
+
+/// A directory that is deleted along with everything in it when dropped
+struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    fn new(parent: &Path) -> io::Result<TempDir> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "tubes-external-bfs-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = parent.join(name);
+        fs::create_dir_all(&path)?;
+        return Ok(TempDir { path });
+    }
+
+    fn layer(&self, depth: usize) -> PathBuf {
+        return self.path.join(format!("layer-{}", depth));
+    }
+
+    fn run(&self, idx: usize) -> PathBuf {
+        return self.path.join(format!("run-{}", idx));
+    }
+
+    fn visited(&self) -> PathBuf {
+        return self.path.join("visited");
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Reads the fixed size records of a file of packed states one at a time
+struct StateReader {
+    reader: BufReader<File>,
+    record_len: usize,
+}
+
+impl StateReader {
+    fn open(path: &Path, record_len: usize) -> io::Result<StateReader> {
+        let reader = BufReader::new(File::open(path)?);
+        return Ok(StateReader { reader, record_len });
+    }
+
+    fn next_state(&mut self) -> io::Result<Option<CompactState>> {
+        let mut record = vec![0; self.record_len];
+        match self.reader.read_exact(&mut record) {
+            Ok(()) => return Ok(Some(CompactState::from_bytes(record.into()))),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Writes a file of packed states
+fn write_states<'a>(
+    path: &Path,
+    states: impl IntoIterator<Item = &'a CompactState>,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for state in states {
+        writer.write_all(state.as_bytes())?;
+    }
+    return writer.flush();
+}
+
+/// Sorts the states, drops duplicates and writes them to a new run file
+fn write_run(
+    dir: &TempDir,
+    runs: &mut Vec<PathBuf>,
+    states: &mut Vec<CompactState>,
+) -> io::Result<()> {
+    states.sort_unstable();
+    states.dedup();
+    let path = dir.run(runs.len());
+    write_states(&path, states.iter())?;
+    runs.push(path);
+    states.clear();
+    return Ok(());
+}
+
+/// Merges the sorted runs into the next layer, leaving out states that are in the
+/// visited file, and rewrites the visited file with the new states added
+///
+/// Returns how many new states there were.
+fn merge_runs(
+    dir: &TempDir,
+    runs: &[PathBuf],
+    next_layer: &Path,
+    record_len: usize,
+) -> io::Result<usize> {
+    let mut readers = runs
+        .iter()
+        .map(|path| StateReader::open(path, record_len))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut heap = BinaryHeap::new();
+    for (idx, reader) in readers.iter_mut().enumerate() {
+        if let Some(state) = reader.next_state()? {
+            heap.push(Reverse((state, idx)));
+        }
+    }
+    let mut visited = StateReader::open(&dir.visited(), record_len)?;
+    let mut next_visited = visited.next_state()?;
+    let merged_path = dir.path.join("visited-merged");
+    let mut merged = BufWriter::new(File::create(&merged_path)?);
+    let mut layer = BufWriter::new(File::create(next_layer)?);
+    let mut last: Option<CompactState> = None;
+    let mut new_count = 0;
+    while let Some(Reverse((state, idx))) = heap.pop() {
+        if let Some(next) = readers[idx].next_state()? {
+            heap.push(Reverse((next, idx)));
+        }
+        // The same state can be in several runs, but they come out of the heap together
+        if last.as_ref() == Some(&state) {
+            continue;
+        }
+        // Copy over the visited states that come before this one
+        while let Some(seen) = next_visited.as_ref().filter(|seen| *seen < &state) {
+            merged.write_all(seen.as_bytes())?;
+            next_visited = visited.next_state()?;
+        }
+        if next_visited.as_ref() != Some(&state) {
+            merged.write_all(state.as_bytes())?;
+            layer.write_all(state.as_bytes())?;
+            new_count += 1;
+        }
+        last = Some(state);
+    }
+    while let Some(seen) = next_visited {
+        merged.write_all(seen.as_bytes())?;
+        next_visited = visited.next_state()?;
+    }
+    merged.flush()?;
+    layer.flush()?;
+    fs::rename(&merged_path, dir.visited())?;
+    for run in runs {
+        fs::remove_file(run)?;
+    }
+    return Ok(new_count);
+}
+
+/// Returns the successors of a state in canonical form
+fn canonical_successors(
+    layout: &CompactLayout,
+    state: &CompactState,
+    config: &SearchConfig,
+) -> Vec<CompactState> {
+    return layout
+        .successors(state, None, config)
+        .into_iter()
+        .map(|(_, next)| layout.canonical(&next))
+        .collect();
+}
+
+fn external_bfs(
+    initial_state: &TubeState,
+    solver: &ExternalBfs,
+    search: &mut SearchContext,
+) -> Result<TubeStateNode, SolveError> {
+    initial_state.validate()?;
+    if initial_state.issolved() {
+        return Ok(TubeStateNode {
+            actions: vec![],
+            state: initial_state.clone(),
+        });
+    }
+    let config = search.config();
+    let layout = CompactLayout::new(initial_state);
+    let record_len = layout.packed_len();
+    let parent = solver.temp_dir.clone().unwrap_or_else(std::env::temp_dir);
+    let dir = TempDir::new(&parent)?;
+    let start = layout.canonical(&layout.encode(initial_state));
+    write_states(&dir.layer(0), [&start])?;
+    write_states(&dir.visited(), [&start])?;
+    let max_states_in_memory = solver.max_states_in_memory.max(1);
+    let mut depth = 0;
+    loop {
+        let mut runs = Vec::new();
+        let mut buffer = Vec::new();
+        let mut generated = 0;
+        let mut layer = StateReader::open(&dir.layer(depth), record_len)?;
+        while let Some(state) = layer.next_state()? {
+            search.expand(depth)?;
+            for next in canonical_successors(&layout, &state, config) {
+                search.generated();
+                generated += 1;
+                if layout.issolved(&next) {
+                    return rebuild_solution(initial_state, &layout, &dir, depth, next, config);
+                }
+                buffer.push(next);
+                if buffer.len() >= max_states_in_memory {
+                    write_run(&dir, &mut runs, &mut buffer)?;
+                }
+            }
+        }
+        if !buffer.is_empty() {
+            write_run(&dir, &mut runs, &mut buffer)?;
+        }
+        let new_count = merge_runs(&dir, &runs, &dir.layer(depth + 1), record_len)?;
+        search.record(0, 0, generated - new_count);
+        search.expanded_to_depth(depth);
+        if new_count == 0 {
+            return Err(SolveError::Unsolvable);
+        }
+        search.frontier(new_count);
+        depth += 1;
+    }
+}
+
+/// Finds the moves from the initial state to `solved`, which is a successor of a state
+/// in layer `depth`
+///
+/// The layers are walked back to the start to find a chain of canonical states, and the
+/// moves between them are then replayed from the initial state so that they use its tube
+/// indices.
+fn rebuild_solution(
+    initial_state: &TubeState,
+    layout: &CompactLayout,
+    dir: &TempDir,
+    depth: usize,
+    solved: CompactState,
+    config: &SearchConfig,
+) -> Result<TubeStateNode, SolveError> {
+    let mut chain = vec![solved];
+    for layer_depth in (0..=depth).rev() {
+        let target = chain.last().unwrap().clone();
+        let mut layer = StateReader::open(&dir.layer(layer_depth), layout.packed_len())?;
+        loop {
+            match layer.next_state()? {
+                Some(state) => {
+                    if canonical_successors(layout, &state, config).contains(&target) {
+                        chain.push(state);
+                        break;
+                    }
+                }
+                None => {
+                    return Err(SolveError::Storage(format!(
+                        "no state in layer {} leads to the next one",
+                        layer_depth
+                    )))
+                }
+            }
+        }
+    }
+    chain.reverse();
+    let mut state = layout.encode(initial_state);
+    let mut actions = Vec::new();
+    for target in &chain[1..] {
+        let (action, next) = layout
+            .successors(&state, None, config)
+            .into_iter()
+            .find(|(_, next)| &layout.canonical(next) == target)
+            .unwrap();
+        actions.push(action);
+        state = next;
+    }
+    return Ok(TubeStateNode {
+        actions,
+        state: layout.decode(&state),
+    });
+}
+
+#[cfg(test)]
+mod external_tests {
+    use super::*;
+    use crate::{load_level, Bfs, Rules, TubeArray};
+
+    /// Returns a directory under the system temporary directory that no other test, or
+    /// other run of the tests, uses
+    fn test_dir(name: &str) -> PathBuf {
+        return std::env::temp_dir().join(format!(
+            "tubes-external-tests-{}-{}",
+            name,
+            std::process::id()
+        ));
+    }
+
+    #[test]
+    fn test_tiny_runs_match_bfs() {
+        let temp_dir = test_dir("tiny-runs");
+        // A handful of states per run makes every layer merge lots of run files
+        let solver = ExternalBfs {
+            temp_dir: Some(temp_dir.clone()),
+            max_states_in_memory: 7,
+        };
+        for rules in Rules::ALL {
+            let config = SearchConfig {
+                rules,
+                ..SearchConfig::default()
+            };
+            for level in [3, 6, 8] {
+                let state = load_level(&format!("levels/{}.toml", level));
+                let expected = Bfs.solve(&state, &config).result.unwrap();
+                let report = solver.solve(&state, &config);
+                let solution = report.result.unwrap();
+                let states = crate::replay_with_rules(&state, &solution.actions, rules).unwrap();
+                assert_eq!(states.last(), Some(&solution.state));
+                assert!(solution.state.issolved());
+                assert_eq!(solution.actions.len(), expected.actions.len());
+                assert!(report.stats.duplicates_pruned > 0);
+            }
+        }
+        // Every temporary directory was cleaned up
+        assert_eq!(fs::read_dir(&temp_dir).unwrap().count(), 0);
+        fs::remove_dir(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn test_unsolvable_and_limits() {
+        let state = load_level("levels/8.toml");
+        let config = SearchConfig {
+            max_nodes: Some(20),
+            ..SearchConfig::default()
+        };
+        let report = ExternalBfs::default().solve(&state, &config);
+        assert_eq!(
+            report.result.err(),
+            Some(SolveError::SearchLimit(crate::Limit::Nodes(20)))
+        );
+        let contents = "tubes = [['blue', 'red', 'blue', 'red'], ['red', 'blue', 'red', 'blue']]";
+        let tube_array: TubeArray = toml::from_str(contents).unwrap();
+        let stuck = TubeState::from_tube_array(tube_array);
+        let report = ExternalBfs::default().solve(&stuck, &SearchConfig::default());
+        assert_eq!(report.result.err(), Some(SolveError::Unsolvable));
+        let contents = "tubes = [['blue', 'blue', 'blue', 'blue'], []]";
+        let tube_array: TubeArray = toml::from_str(contents).unwrap();
+        let solved = TubeState::from_tube_array(tube_array);
+        let report = ExternalBfs::default().solve(&solved, &SearchConfig::default());
+        assert_eq!(report.result.unwrap().actions.len(), 0);
+    }
+
+    #[test]
+    fn test_missing_temp_dir_parent_is_created() {
+        let temp_dir = test_dir("nested").join("deeper");
+        let solver = ExternalBfs {
+            temp_dir: Some(temp_dir.clone()),
+            ..ExternalBfs::default()
+        };
+        let state = load_level("levels/3.toml");
+        assert!(solver
+            .solve(&state, &SearchConfig::default())
+            .result
+            .is_ok());
+        let _ = fs::remove_dir_all(temp_dir.parent().unwrap());
+    }
+}
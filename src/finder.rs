@@ -1,7 +1,7 @@
 use image;
 use imageproc::template_matching;
 use std::{cmp::Ordering, path::Path};
-use tubes::{LiquidColor, Tube, TubeState};
+use tubes::{infer_capacity, LiquidColor, Palette, Tube, TubeState, DEFAULT_CAPACITY};
 
 fn find_local_minima<T>(img: &image::ImageBuffer<image::Luma<T>, Vec<T>>) -> Vec<(u32, u32)>
 where
@@ -163,34 +163,89 @@ fn point_cmp(a: &(u32, u32), b: &(u32, u32)) -> Ordering {
     }
 }
 
+/// The most layers a tube in a screenshot is checked for
+const MAX_SCANNED_LAYERS: i32 = 8;
+
+/// Empty space in a tube is a dark gray, hidden layers are drawn in a lighter one
+const MAX_EMPTY_SHADE: u8 = 60;
+
+/// How far apart the gray of two hidden layers can be and still look alike
+const HIDDEN_SHADE_TOLERANCE: u8 = 8;
+
+/// Reads the liquid in each tube and works out how many layers the tubes hold
+///
+/// Layers are read from the bottom of each tube up until the first empty one above the
+/// liquid. Hidden layers are a lighter gray than empty space, and they count as hidden
+/// when they all look alike and have liquid above them. The capacity of the
+/// tubes is then the smallest one that fits all of the liquid, see `infer_capacity`.
+///
+/// Colors are named by their hex codes, and the palette of those names is returned
+/// along with the tubes.
+///
+/// With `debug_image_path` a copy of the screenshot is saved there with every pixel
+/// that was read marked in red.
 pub fn extract_tube_colors(
     level_img: &image::RgbImage,
     tube_centers: Vec<(u32, u32)>,
-) -> TubeState {
+    debug_image_path: Option<&Path>,
+) -> (TubeState, Palette) {
     let mut tube_centers = tube_centers.clone();
     tube_centers.sort_by(|a, b| point_cmp(a, b));
     let color_spacing = 11;
-    let mut tubes = Vec::new();
+    let bottom_layer = 2;
+    let mut tube_colors = Vec::new();
     let mut new_level_img = level_img.clone();
     for (x, y) in tube_centers {
-        let mut tube_colors = Vec::new();
-        for layer in [-1, 0, 1, 2] {
-            let dy = layer * color_spacing as i32;
-            let color = level_img.get_pixel(x, (y as i32 + dy) as u32);
-            new_level_img.put_pixel(x, (y as i32 + dy) as u32, image::Rgb([255, 0, 0]));
-            let liquid_color = LiquidColor::new(color[0], color[1], color[2]);
-            tube_colors.push(liquid_color);
+        let mut colors = Vec::new();
+        let mut hidden_count = 0;
+        let mut hidden_shade = None;
+        for layer in 0..MAX_SCANNED_LAYERS {
+            let py = y as i32 + (bottom_layer - layer) * color_spacing;
+            if py < 0 {
+                break;
+            }
+            let color = level_img.get_pixel(x, py as u32);
+            new_level_img.put_pixel(x, py as u32, image::Rgb([255, 0, 0]));
+            // Empty space and hidden layers are gray
+            if color[0] == color[1] && color[1] == color[2] {
+                if !colors.is_empty() || color[0] <= MAX_EMPTY_SHADE {
+                    break;
+                }
+                match hidden_shade {
+                    None => hidden_shade = Some(color[0]),
+                    Some(shade) if color[0].abs_diff(shade) <= HIDDEN_SHADE_TOLERANCE => {}
+                    Some(_) => break,
+                }
+                hidden_count += 1;
+                continue;
+            }
+            if colors.is_empty() {
+                colors.resize(hidden_count, String::from("?"));
+            }
+            colors.push(hex::encode([color[0], color[1], color[2]]));
         }
-        tubes.push(Tube::from_vec(tube_colors));
+        // Tubes list their liquid from the top down
+        colors.reverse();
+        tube_colors.push(colors);
+    }
+    if let Some(debug_image_path) = debug_image_path {
+        let _ = new_level_img.save(debug_image_path);
     }
-    let _ = new_level_img.save("tube_color_locations.png");
-    return TubeState { tubes };
+    let palette = Palette::from_names(tube_colors.iter().flatten().map(|name| name.as_str()));
+    let tube_colors: Vec<Vec<LiquidColor>> = tube_colors
+        .iter()
+        .map(|names| names.iter().map(|n| palette.color(n).unwrap()).collect())
+        .collect();
+    let capacity = infer_capacity(&tube_colors).unwrap_or(DEFAULT_CAPACITY);
+    let tubes = tube_colors
+        .into_iter()
+        .map(|colors| Tube::from_vec_with_capacity(colors, capacity))
+        .collect();
+    return (TubeState { tubes }, palette);
 }
 
 #[cfg(test)]
 mod matching_tests {
-    use tubes::EMPTY_TUBE;
-
     use super::*;
 
     #[test]
@@ -219,18 +274,18 @@ mod matching_tests {
             println!("{:?}", c);
         }
         let _ = display_level_img.save("result_level_8.png");
-        let tubes = extract_tube_colors(&level_img, tube_centers.clone());
-        println!("{}", tubes);
+        let (tubes, palette) = extract_tube_colors(&level_img, tube_centers.clone(), None);
+        println!("{}", tubes.display(&palette));
         for i in &tubes.tubes {
             for c in &i.layers {
                 println!("{:?}", c);
             }
         }
-        let green = LiquidColor::new(129, 211, 133);
-        let orange = LiquidColor::new(219, 144, 81);
-        let blue = LiquidColor::new(56, 46, 187);
-        let pink = LiquidColor::new(217, 103, 124);
-        let red = LiquidColor::new(181, 57, 45);
+        let green = palette.color("81d385").unwrap();
+        let orange = palette.color("db9051").unwrap();
+        let blue = palette.color("382ebb").unwrap();
+        let pink = palette.color("d9677c").unwrap();
+        let red = palette.color("b5392d").unwrap();
         let expected_tubes = TubeState {
             tubes: vec![
                 Tube::new(blue.clone(), green.clone(), orange.clone(), green.clone()),
@@ -238,11 +293,48 @@ mod matching_tests {
                 Tube::new(red.clone(), blue.clone(), red.clone(), pink.clone()),
                 Tube::new(pink.clone(), green.clone(), red.clone(), blue.clone()),
                 Tube::new(orange.clone(), red.clone(), green.clone(), blue.clone()),
-                EMPTY_TUBE,
-                EMPTY_TUBE,
+                Tube::empty(DEFAULT_CAPACITY),
+                Tube::empty(DEFAULT_CAPACITY),
+            ],
+        };
+        assert_eq!(tubes, expected_tubes);
+        println!(
+            "{}",
+            toml::to_string(&tubes.to_tube_array(&palette)).unwrap()
+        )
+    }
+
+    #[test]
+    fn test_hidden_layers_are_scanned() {
+        // Three tubes that hold three layers, drawn the way extract_tube_colors reads them
+        let mut level_img = image::RgbImage::from_pixel(20, 60, image::Rgb([26, 26, 26]));
+        let hidden = image::Rgb([110, 110, 110]);
+        for (x, top) in [
+            (5, image::Rgb([56, 46, 187])),
+            (10, image::Rgb([181, 57, 45])),
+        ] {
+            level_img.put_pixel(x, 52, hidden);
+            level_img.put_pixel(x, 41, hidden);
+            level_img.put_pixel(x, 30, top);
+        }
+        let (tubes, palette) =
+            extract_tube_colors(&level_img, vec![(5, 30), (10, 30), (15, 30)], None);
+        assert_eq!(palette.len(), 2);
+        let blue = palette.color("382ebb").unwrap();
+        let red = palette.color("b5392d").unwrap();
+        let expected_tubes = TubeState {
+            tubes: vec![
+                Tube::from_vec_with_capacity(
+                    vec![blue, LiquidColor::Unknown, LiquidColor::Unknown],
+                    3,
+                ),
+                Tube::from_vec_with_capacity(
+                    vec![red, LiquidColor::Unknown, LiquidColor::Unknown],
+                    3,
+                ),
+                Tube::empty(3),
             ],
         };
         assert_eq!(tubes, expected_tubes);
-        println!("{}", toml::to_string(&tubes.to_tube_array()).unwrap())
     }
 }
@@ -0,0 +1,903 @@
+use crate::pruning::is_pruned;
+use crate::search::SearchContext;
+use crate::{
+    hint, ColorId, Limit, LiquidColor, Rules, SearchConfig, SolveError, TransferAction, TubeState,
+};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// The most ways of filling in the hidden layers that `one_move_hidden_hint` will consider
+pub const MAX_POSSIBILITIES: usize = 10_000;
+
+/// How `one_move_hidden_hint` picks a move when some layers of liquid are hidden
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum HiddenStrategy {
+    /// Pick the move that keeps the level solvable for the most fillings of the hidden
+    /// layers, with the fewest moves left when the colors turn out as badly as they can
+    #[default]
+    Robust,
+    /// Pick the move that keeps the level solvable for the most fillings of the hidden
+    /// layers and could reveal the most about them
+    Informative,
+    /// Only pick a move that leads to a solution however the hidden layers are filled in,
+    /// planning every later move from what the earlier ones reveal, see `safe_hidden_hint`
+    Safe,
+}
+
+impl HiddenStrategy {
+    pub const ALL: [HiddenStrategy; 3] = [
+        HiddenStrategy::Robust,
+        HiddenStrategy::Informative,
+        HiddenStrategy::Safe,
+    ];
+
+    pub fn name(&self) -> &str {
+        match self {
+            HiddenStrategy::Robust => return "robust",
+            HiddenStrategy::Informative => return "informative",
+            HiddenStrategy::Safe => return "safe",
+        }
+    }
+}
+
+impl fmt::Display for HiddenStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for HiddenStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        for strategy in HiddenStrategy::ALL {
+            if strategy.name() == s {
+                return Ok(strategy);
+            }
+        }
+        let names: Vec<&str> = HiddenStrategy::ALL.iter().map(|s| s.name()).collect();
+        return Err(format!(
+            "unknown strategy {}, expected one of: {}",
+            s,
+            names.join(", ")
+        ));
+    }
+}
+
+/// A move to make while some layers are hidden and what it could lead to
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HiddenHint {
+    /// The move to make, or `None` if the state is already solved
+    pub action: Option<TransferAction>,
+    /// How many ways the hidden layers could be filled in
+    pub possibilities: usize,
+    /// For how many of those the level could still be solved after the move by someone
+    /// who then knew every hidden color, or by following `safe_hidden_hint` for
+    /// `HiddenStrategy::Safe`
+    pub winnable: usize,
+    /// The most moves a solution takes, including `action`, over the fillings the level
+    /// can still be solved for, again as if every hidden color were known unless the
+    /// strategy is `HiddenStrategy::Safe`
+    pub worst_moves_left: usize,
+    /// How many different states the player could see after the move
+    pub outcomes: usize,
+}
+
+impl TubeState {
+    /// Returns how many layers of liquid are hidden
+    pub fn hidden_count(&self) -> usize {
+        return self
+            .tubes
+            .iter()
+            .flat_map(|t| &t.layers)
+            .filter(|l| *l == &LiquidColor::Unknown)
+            .count();
+    }
+}
+
+/// The colors that make up the hidden layers of a state
+struct HiddenColors {
+    /// Each color that can be seen and how many layers it is short of filling its last
+    /// tube
+    missing: Vec<(LiquidColor, usize)>,
+    /// How many colors can't be seen anywhere, each of which fills a whole tube
+    unseen: usize,
+    capacity: usize,
+}
+
+impl HiddenColors {
+    /// Works out the hidden colors of a state from the colors that can be seen
+    ///
+    /// In the end every color fills a whole number of tubes, so the hidden layers hold
+    /// whatever each visible color is short of filling its last tube, and any layers left
+    /// over belong to colors that are hidden everywhere.
+    fn of(state: &TubeState) -> Result<HiddenColors, SolveError> {
+        let capacity = state.tubes.first().map_or(0, |t| t.capacity());
+        if state.tubes.iter().any(|t| t.capacity() != capacity) {
+            return Err(SolveError::InvalidInput(String::from(
+                "hidden layers are only supported when every tube holds the same amount",
+            )));
+        }
+        let mut hidden = 0;
+        let mut color_counts: HashMap<&LiquidColor, usize> = HashMap::new();
+        for layer in state.tubes.iter().flat_map(|t| &t.layers) {
+            match layer {
+                LiquidColor::Empty => {}
+                LiquidColor::Unknown => hidden += 1,
+                color => *color_counts.entry(color).or_insert(0) += 1,
+            }
+        }
+        let mut missing: Vec<(LiquidColor, usize)> = color_counts
+            .into_iter()
+            .map(|(color, count)| (color.clone(), (capacity - count % capacity) % capacity))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        missing.sort();
+        let missing_count: usize = missing.iter().map(|(_, count)| count).sum();
+        if missing_count > hidden || (hidden - missing_count) % capacity != 0 {
+            return Err(SolveError::InvalidInput(format!(
+                "{} layers are hidden but the visible colors are {} layers short of filling their tubes, which leaves layers that don't fill whole tubes",
+                hidden, missing_count
+            )));
+        }
+        return Ok(HiddenColors {
+            missing,
+            unseen: (hidden - missing_count) / capacity,
+            capacity,
+        });
+    }
+
+    /// Returns how many ways the hidden layers can be filled in, up to `usize::MAX`
+    fn fillings(&self) -> usize {
+        let mut groups: Vec<usize> = self.missing.iter().map(|(_, count)| *count).collect();
+        groups.resize(groups.len() + self.unseen, self.capacity);
+        let mut remaining: u128 = groups.iter().sum::<usize>() as u128;
+        let mut count: u128 = 1;
+        for group in groups {
+            // Choosing which of the remaining layers hold this color
+            for i in 0..group as u128 {
+                count = match count.checked_mul(remaining - i) {
+                    Some(count) => count / (i + 1),
+                    None => return usize::MAX,
+                };
+            }
+            remaining -= group as u128;
+        }
+        // The unseen colors can stand in for each other
+        for i in 1..=self.unseen as u128 {
+            count /= i;
+        }
+        return usize::try_from(count).unwrap_or(usize::MAX);
+    }
+}
+
+/// Returns the color the `i`th color that can't be seen anywhere in the state stands for
+///
+/// Colors that are hidden everywhere have no name and any of them could be swapped for
+/// any other, so they are numbered after every palette color the state uses.
+fn unseen_color(state: &TubeState, i: usize) -> LiquidColor {
+    let first = state
+        .tubes
+        .iter()
+        .flat_map(|t| &t.layers)
+        .filter_map(|layer| match layer {
+            LiquidColor::Other(ColorId(id)) => Some(id + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+    return LiquidColor::Other(ColorId(first + i as u32));
+}
+
+/// Returns every way the hidden layers of the state could be filled in
+///
+/// In the end every color fills a whole number of tubes, so the hidden layers hold
+/// whatever each visible color is short of filling its last tube. Layers left over after
+/// that belong to colors that can't be seen anywhere, which are given colors of their own
+/// after the palette colors. Those colors are interchangeable, so fillings that only swap
+/// them are listed once. This only works when every tube holds the same amount. There
+/// can't be more than `MAX_POSSIBILITIES`; `safe_hidden_hint` doesn't need to list them.
+///
+/// ```
+/// use tubes::{hidden_possibilities, LiquidColor, Tube, TubeState};
+/// let state = TubeState {
+///     tubes: vec![
+///         Tube::from_vec_with_capacity(vec![LiquidColor::Blue, LiquidColor::Unknown], 2),
+///         Tube::from_vec_with_capacity(vec![LiquidColor::Red, LiquidColor::Unknown], 2),
+///         Tube::empty(2),
+///     ],
+/// };
+/// // The hidden layers are one blue and one red, in either order
+/// assert_eq!(hidden_possibilities(&state).unwrap().len(), 2);
+///
+/// let tops_only = TubeState {
+///     tubes: vec![
+///         Tube::from_vec_with_capacity(vec![LiquidColor::Blue, LiquidColor::Unknown], 2),
+///         Tube::from_vec_with_capacity(vec![LiquidColor::Red, LiquidColor::Unknown], 2),
+///         Tube::from_vec_with_capacity(vec![LiquidColor::Unknown, LiquidColor::Unknown], 2),
+///         Tube::empty(2),
+///     ],
+/// };
+/// // Blue, red and a color that can't be seen anywhere fill the hidden layers
+/// assert_eq!(hidden_possibilities(&tops_only).unwrap().len(), 12);
+/// ```
+pub fn hidden_possibilities(state: &TubeState) -> Result<Vec<TubeState>, SolveError> {
+    let mut hidden = Vec::new();
+    for (ti, tube) in state.tubes.iter().enumerate() {
+        for (li, layer) in tube.layers.iter().enumerate() {
+            if layer == &LiquidColor::Unknown {
+                hidden.push((ti, li));
+            }
+        }
+    }
+    if hidden.is_empty() {
+        return Ok(vec![state.clone()]);
+    }
+    let colors = HiddenColors::of(state)?;
+    let mut missing = colors.missing.clone();
+    let first_unseen = missing.len();
+    for i in 0..colors.unseen {
+        missing.push((unseen_color(state, i), colors.capacity));
+    }
+    let mut possibilities = Vec::new();
+    fill_hidden(
+        &mut state.clone(),
+        &hidden,
+        &mut missing,
+        first_unseen,
+        &mut possibilities,
+    )?;
+    return Ok(possibilities);
+}
+
+/// Tries every color that is still missing in the first hidden layer and fills in the
+/// rest recursively
+///
+/// The colors from `first_unseen` on can't be seen anywhere, so one of them is only
+/// tried once the one before it has been used, which lists fillings that just swap them
+/// once.
+fn fill_hidden(
+    state: &mut TubeState,
+    hidden: &[(usize, usize)],
+    missing: &mut Vec<(LiquidColor, usize)>,
+    first_unseen: usize,
+    possibilities: &mut Vec<TubeState>,
+) -> Result<(), SolveError> {
+    let ((ti, li), rest) = match hidden.split_first() {
+        Some(split) => split,
+        None => {
+            if possibilities.len() == MAX_POSSIBILITIES {
+                return Err(SolveError::SearchLimit(Limit::Possibilities(
+                    MAX_POSSIBILITIES,
+                )));
+            }
+            possibilities.push(state.clone());
+            return Ok(());
+        }
+    };
+    let capacity = state.tubes[*ti].capacity();
+    for i in 0..missing.len() {
+        if missing[i].1 == 0 {
+            continue;
+        }
+        if i > first_unseen && missing[i - 1].1 == capacity {
+            continue;
+        }
+        missing[i].1 -= 1;
+        state.tubes[*ti].layers[*li] = missing[i].0.clone();
+        fill_hidden(state, rest, missing, first_unseen, possibilities)?;
+        missing[i].1 += 1;
+    }
+    state.tubes[*ti].layers[*li] = LiquidColor::Unknown;
+    return Ok(());
+}
+
+/// Returns what the player sees of `next`, which a move reached from the observed state
+/// `observed`
+///
+/// Layers that were hidden stay hidden unless they end up on top of their tube.
+fn observe(observed: &TubeState, next: &TubeState) -> TubeState {
+    let mut seen = next.clone();
+    for (tube, observed_tube) in seen.tubes.iter_mut().zip(&observed.tubes) {
+        let top = tube.howempty() as usize;
+        for (li, layer) in tube.layers.iter_mut().enumerate() {
+            if observed_tube.layers[li] == LiquidColor::Unknown
+                && layer != &LiquidColor::Empty
+                && li != top
+            {
+                *layer = LiquidColor::Unknown;
+            }
+        }
+    }
+    return seen;
+}
+
+/// Picks a move for a state with hidden layers by looking one move ahead
+///
+/// Each move is tried with every way the hidden layers could be filled in, see
+/// `hidden_possibilities`. A filling counts as winnable after the move if a solution
+/// exists once its colors are known, and the number of distinct states the player could
+/// see afterwards is how informative the move is. Only the first move is judged
+/// against what the player can actually see; the rest of each solution is planned as if
+/// every hidden color were known, so a move that is winnable for every filling can still
+/// leave the player guessing later on. Both strategies first keep the move
+/// winnable for as many fillings as possible, then `HiddenStrategy::Robust` prefers the
+/// fewest moves left at worst and `HiddenStrategy::Informative` prefers the most outcomes.
+/// `HiddenStrategy::Safe` doesn't look at the fillings one by one and plans every move
+/// against what the player can see instead, see `safe_hidden_hint`.
+/// The config applies to every search, and if no move is winnable for any filling the
+/// error is `SolveError::Unsolvable`.
+///
+/// ```
+/// use tubes::{one_move_hidden_hint, HiddenStrategy, LiquidColor, SearchConfig, Tube, TubeState};
+/// let state = TubeState {
+///     tubes: vec![
+///         Tube::from_vec_with_capacity(vec![LiquidColor::Blue, LiquidColor::Unknown], 2),
+///         Tube::from_vec_with_capacity(vec![LiquidColor::Red, LiquidColor::Unknown], 2),
+///         Tube::empty(2),
+///     ],
+/// };
+/// let next = one_move_hidden_hint(&state, HiddenStrategy::Robust, &SearchConfig::default()).unwrap();
+/// assert!(next.action.is_some());
+/// assert_eq!(next.winnable, next.possibilities);
+/// ```
+pub fn one_move_hidden_hint(
+    state: &TubeState,
+    strategy: HiddenStrategy,
+    config: &SearchConfig,
+) -> Result<HiddenHint, SolveError> {
+    if strategy == HiddenStrategy::Safe {
+        return safe_hidden_hint(state, config);
+    }
+    let possibilities = hidden_possibilities(state)?;
+    if state.issolved() && state.hidden_count() == 0 {
+        return Ok(HiddenHint {
+            action: None,
+            possibilities: 1,
+            winnable: 1,
+            worst_moves_left: 0,
+            outcomes: 1,
+        });
+    }
+    // Many fillings lead to the same state after a move, so each is only solved once
+    let mut moves_left: HashMap<TubeState, Option<usize>> = HashMap::new();
+    let mut candidates = Vec::new();
+    for send_idx in 0..state.tubes.len() {
+        for recv_idx in 0..state.tubes.len() {
+            if send_idx == recv_idx {
+                continue;
+            }
+            let action = TransferAction::new(send_idx, recv_idx);
+            let mut winnable = 0;
+            let mut worst_moves_left = 0;
+            let mut outcomes = HashSet::new();
+            for possibility in &possibilities {
+                let next_state = match possibility.apply_with_rules(&action, config.rules) {
+                    Ok(next_state) => next_state,
+                    Err(_) => continue,
+                };
+                outcomes.insert(observe(state, &next_state));
+                let key = next_state.canonical();
+                let left = match moves_left.get(&key) {
+                    Some(left) => *left,
+                    None => {
+                        let left = match hint(&next_state, config) {
+                            Ok(next) => Some(next.moves_left),
+                            Err(SolveError::Unsolvable) => None,
+                            Err(e) => return Err(e),
+                        };
+                        moves_left.insert(key, left);
+                        left
+                    }
+                };
+                if let Some(left) = left {
+                    winnable += 1;
+                    worst_moves_left = worst_moves_left.max(left + 1);
+                }
+            }
+            if winnable > 0 {
+                candidates.push(HiddenHint {
+                    action: Some(action),
+                    possibilities: possibilities.len(),
+                    winnable,
+                    worst_moves_left,
+                    outcomes: outcomes.len(),
+                });
+            }
+        }
+    }
+    let best = match strategy {
+        HiddenStrategy::Robust => candidates.into_iter().min_by_key(|c| {
+            return (Reverse(c.winnable), c.worst_moves_left, Reverse(c.outcomes));
+        }),
+        HiddenStrategy::Informative => candidates.into_iter().min_by_key(|c| {
+            return (Reverse(c.winnable), Reverse(c.outcomes), c.worst_moves_left);
+        }),
+        HiddenStrategy::Safe => unreachable!(),
+    };
+    return best.ok_or(SolveError::Unsolvable);
+}
+
+/// Picks a move for a state with hidden layers that leads to a solution however the
+/// hidden layers are filled in
+///
+/// The search works on what the player can see. A move that pours off the top of a tube
+/// with hidden layers below can reveal any color that could be there, and the move is
+/// only good if every state the player could see afterwards is good too, so each later
+/// move is planned knowing only what the earlier ones revealed. The search deepens until
+/// it finds the fewest moves that solve the level at worst, which is `worst_moves_left`.
+/// Colors that can't be seen anywhere are stood in for by colors of their own, see
+/// `hidden_possibilities`, but the fillings are never listed, so there is no limit on
+/// how many there can be. The config limits the number of states the search expands,
+/// and if no plan works for every filling the error is `SolveError::Unsolvable`.
+///
+/// ```
+/// use tubes::{safe_hidden_hint, LiquidColor, SearchConfig, Tube, TubeState};
+/// let hidden = LiquidColor::Unknown;
+/// let tops_only = TubeState {
+///     tubes: vec![
+///         Tube::from_vec_with_capacity(vec![LiquidColor::Blue, hidden.clone()], 2),
+///         Tube::from_vec_with_capacity(vec![LiquidColor::Red, hidden], 2),
+///         Tube::empty(2),
+///     ],
+/// };
+/// let next = safe_hidden_hint(&tops_only, &SearchConfig::default()).unwrap();
+/// assert!(next.action.is_some());
+/// assert_eq!(next.winnable, next.possibilities);
+/// assert_eq!(next.worst_moves_left, 3);
+/// ```
+pub fn safe_hidden_hint(
+    state: &TubeState,
+    config: &SearchConfig,
+) -> Result<HiddenHint, SolveError> {
+    let colors = HiddenColors::of(state)?;
+    let possibilities = colors.fillings();
+    let mut planner = SafePlanner {
+        search: SearchContext::new(config),
+        failed: HashMap::new(),
+        path: HashSet::new(),
+    };
+    let mut budget = seen_lower_bound(state);
+    loop {
+        match planner.plan(state, None, budget, 0)? {
+            Plan::Found(action) => {
+                let outcomes = match &action {
+                    Some(action) => {
+                        reveal_outcomes(state, action, config.rules).map_or(0, |o| o.len())
+                    }
+                    None => 1,
+                };
+                return Ok(HiddenHint {
+                    action,
+                    possibilities,
+                    winnable: possibilities,
+                    worst_moves_left: budget,
+                    outcomes,
+                });
+            }
+            Plan::Failed { cut_off: false, .. } => return Err(SolveError::Unsolvable),
+            Plan::Failed { cut_off: true, .. } => budget += 1,
+        }
+    }
+}
+
+/// The result of planning from a state the player can see
+enum Plan {
+    /// The move to make now, or `None` if the level is already solved
+    Found(Option<TransferAction>),
+    /// No plan fits in the moves left. `cut_off` is set if one might with more moves,
+    /// and `looped` if a plan was skipped because it came back to a state earlier on the
+    /// path, so the failure only holds for this path.
+    Failed { cut_off: bool, looped: bool },
+}
+
+/// The state of a run of `safe_hidden_hint`
+struct SafePlanner<'a> {
+    search: SearchContext<'a>,
+    /// The most moves that were too few to solve each canonical state, or `None` if no
+    /// number of moves is enough
+    failed: HashMap<TubeState, Option<usize>>,
+    /// The canonical states on the path being planned
+    path: HashSet<TubeState>,
+}
+
+impl<'a> SafePlanner<'a> {
+    /// Looks for a move from `seen` after which every state the player could see next
+    /// can be solved in `budget - 1` moves or fewer
+    fn plan(
+        &mut self,
+        seen: &TubeState,
+        previous: Option<&TransferAction>,
+        budget: usize,
+        depth: usize,
+    ) -> Result<Plan, SolveError> {
+        if solved_for_every_filling(seen)? {
+            return Ok(Plan::Found(None));
+        }
+        let key = seen.canonical();
+        if self.path.contains(&key) {
+            return Ok(Plan::Failed {
+                cut_off: false,
+                looped: true,
+            });
+        }
+        match self.failed.get(&key) {
+            Some(None) => {
+                return Ok(Plan::Failed {
+                    cut_off: false,
+                    looped: false,
+                })
+            }
+            Some(Some(failed_budget)) if budget <= *failed_budget => {
+                return Ok(Plan::Failed {
+                    cut_off: true,
+                    looped: false,
+                })
+            }
+            _ => {}
+        }
+        if budget == 0 || seen_lower_bound(seen) > budget {
+            return Ok(Plan::Failed {
+                cut_off: true,
+                looped: false,
+            });
+        }
+        self.search.expand(depth)?;
+        self.path.insert(key.clone());
+        let mut cut_off = false;
+        let mut looped = false;
+        let mut found = None;
+        for send_idx in 0..seen.tubes.len() {
+            for recv_idx in 0..seen.tubes.len() {
+                let action = TransferAction::new(send_idx, recv_idx);
+                if send_idx == recv_idx || is_pruned(seen, &action, previous) {
+                    continue;
+                }
+                let outcomes = match reveal_outcomes(seen, &action, self.search.config().rules) {
+                    Some(outcomes) => outcomes,
+                    None => continue,
+                };
+                let mut every_outcome = true;
+                for outcome in &outcomes {
+                    self.search.generated();
+                    // Pouring back is only pointless if the move revealed nothing
+                    let revealed = outcome.hidden_count() < seen.hidden_count();
+                    let previous = if revealed { None } else { Some(&action) };
+                    if let Plan::Failed {
+                        cut_off: outcome_cut_off,
+                        looped: outcome_looped,
+                    } = self.plan(outcome, previous, budget - 1, depth + 1)?
+                    {
+                        cut_off |= outcome_cut_off;
+                        looped |= outcome_looped;
+                        every_outcome = false;
+                        break;
+                    }
+                }
+                if every_outcome {
+                    found = Some(action);
+                    break;
+                }
+            }
+            if found.is_some() {
+                break;
+            }
+        }
+        self.path.remove(&key);
+        if found.is_some() {
+            return Ok(Plan::Found(found));
+        }
+        if !looped {
+            self.failed
+                .insert(key, if cut_off { Some(budget) } else { None });
+        }
+        return Ok(Plan::Failed { cut_off, looped });
+    }
+}
+
+/// Returns true if the level is solved whatever the hidden layers turn out to be
+fn solved_for_every_filling(seen: &TubeState) -> Result<bool, SolveError> {
+    let hidden_count = seen.hidden_count();
+    if hidden_count == 0 {
+        return Ok(seen.issolved());
+    }
+    // With more than one way to fill them in, two hidden layers could swap colors
+    let colors = HiddenColors::of(seen)?;
+    if colors.fillings() != 1 {
+        return Ok(false);
+    }
+    let only_color = match colors.missing.first() {
+        Some((color, _)) => color.clone(),
+        None => unseen_color(seen, 0),
+    };
+    let mut filled = seen.clone();
+    for layer in filled.tubes.iter_mut().flat_map(|t| &mut t.layers) {
+        if layer == &LiquidColor::Unknown {
+            *layer = only_color.clone();
+        }
+    }
+    return Ok(filled.issolved());
+}
+
+/// Returns the fewest moves any filling of the hidden layers needs
+///
+/// Every place where two layers that can be seen differ has to be poured apart at some
+/// point, whatever is hidden.
+fn seen_lower_bound(seen: &TubeState) -> usize {
+    let mut boundaries = 0;
+    for tube in &seen.tubes {
+        for pair in tube.layers.windows(2) {
+            let known = |layer: &LiquidColor| {
+                return layer != &LiquidColor::Empty && layer != &LiquidColor::Unknown;
+            };
+            if known(&pair[0]) && known(&pair[1]) && pair[0] != pair[1] {
+                boundaries += 1;
+            }
+        }
+    }
+    return boundaries;
+}
+
+/// Returns every state the player could see after making `action` from `seen`, or
+/// `None` if the move can't be made
+///
+/// Pouring off the top of a tube can uncover hidden layers below it. Any color that
+/// is still missing could be there, or a color that can't be seen anywhere yet. A
+/// hidden layer of the poured color joins the pour, so the layer below that is
+/// uncovered as well when there is room for it.
+fn reveal_outcomes(
+    seen: &TubeState,
+    action: &TransferAction,
+    rules: Rules,
+) -> Option<Vec<TubeState>> {
+    let send_tube = &seen.tubes[action.send_idx()];
+    let (_, top) = send_tube.topcolor();
+    if top == LiquidColor::Empty || top == LiquidColor::Unknown {
+        return None;
+    }
+    let colors = HiddenColors::of(seen).ok()?;
+    let mut missing = colors.missing;
+    if colors.unseen > 0 {
+        missing.push((unseen_color(seen, 0), colors.capacity));
+    }
+    let below_top = send_tube
+        .layers
+        .iter()
+        .position(|l| l != &LiquidColor::Empty && l != &top)
+        .unwrap_or(send_tube.capacity());
+    let mut fillings = Vec::new();
+    fill_below(
+        &mut seen.clone(),
+        action.send_idx(),
+        below_top,
+        &top,
+        &mut missing,
+        &mut fillings,
+    );
+    let mut outcomes = Vec::new();
+    for filling in fillings {
+        let next = filling.apply_with_rules(action, rules).ok()?;
+        let outcome = observe(seen, &next);
+        if !outcomes.contains(&outcome) {
+            outcomes.push(outcome);
+        }
+    }
+    return Some(outcomes);
+}
+
+/// Fills in the hidden layers of a tube from layer `li` down for as long as they could
+/// be poured along with the `top` color, and adds each way of doing it to `fillings`
+fn fill_below(
+    state: &mut TubeState,
+    ti: usize,
+    li: usize,
+    top: &LiquidColor,
+    missing: &mut Vec<(LiquidColor, usize)>,
+    fillings: &mut Vec<TubeState>,
+) {
+    if state.tubes[ti].layers.get(li) != Some(&LiquidColor::Unknown) {
+        fillings.push(state.clone());
+        return;
+    }
+    for i in 0..missing.len() {
+        if missing[i].1 == 0 {
+            continue;
+        }
+        missing[i].1 -= 1;
+        state.tubes[ti].layers[li] = missing[i].0.clone();
+        if &missing[i].0 == top {
+            fill_below(state, ti, li + 1, top, missing, fillings);
+        } else {
+            fillings.push(state.clone());
+        }
+        missing[i].1 += 1;
+    }
+    state.tubes[ti].layers[li] = LiquidColor::Unknown;
+}
+
+#[cfg(test)]
+mod hidden_tests {
+    use super::*;
+    use crate::{load_level, Tube};
+
+    #[test]
+    fn test_strategy_names() {
+        for strategy in HiddenStrategy::ALL {
+            assert_eq!(strategy.name().parse(), Ok(strategy));
+        }
+        assert!("lucky".parse::<HiddenStrategy>().is_err());
+    }
+
+    #[test]
+    fn test_possibilities_fill_every_tube() {
+        let state = load_level("levels/hidden.toml");
+        assert_eq!(state.hidden_count(), 6);
+        assert!(matches!(state.validate(), Err(SolveError::InvalidInput(_))));
+        let possibilities = hidden_possibilities(&state).unwrap();
+        // Two of each of three colors are hidden, which can be arranged 6! / 2!^3 ways
+        assert_eq!(possibilities.len(), 90);
+        let distinct: HashSet<&TubeState> = possibilities.iter().collect();
+        assert_eq!(distinct.len(), possibilities.len());
+        for possibility in &possibilities {
+            assert_eq!(possibility.hidden_count(), 0);
+            assert!(possibility.validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_hidden_layers_stay_hidden_until_exposed() {
+        let blue = LiquidColor::Blue;
+        let red = LiquidColor::Red;
+        let hidden = LiquidColor::Unknown;
+        let observed = TubeState {
+            tubes: vec![
+                Tube::from_vec_with_capacity(vec![blue.clone(), hidden.clone(), hidden], 3),
+                Tube::from_vec_with_capacity(vec![red.clone(), red.clone()], 3),
+                Tube::empty(3),
+            ],
+        };
+        let actual = TubeState {
+            tubes: vec![
+                Tube::from_vec_with_capacity(vec![blue.clone(), red.clone(), blue.clone()], 3),
+                Tube::from_vec_with_capacity(vec![red.clone(), red.clone()], 3),
+                Tube::empty(3),
+            ],
+        };
+        let next_state = actual.apply(&TransferAction::new(0, 2)).unwrap();
+        let expected = TubeState {
+            tubes: vec![
+                Tube::from_vec_with_capacity(vec![red.clone(), LiquidColor::Unknown], 3),
+                Tube::from_vec_with_capacity(vec![red.clone(), red], 3),
+                Tube::from_vec_with_capacity(vec![blue], 3),
+            ],
+        };
+        assert_eq!(observe(&observed, &next_state), expected);
+    }
+
+    #[test]
+    fn test_robust_move_is_safe_for_every_filling() {
+        let state = load_level("levels/hidden.toml");
+        let config = SearchConfig::default();
+        let possibilities = hidden_possibilities(&state).unwrap();
+        for strategy in [HiddenStrategy::Robust, HiddenStrategy::Informative] {
+            let next = one_move_hidden_hint(&state, strategy, &config).unwrap();
+            let action = next.action.unwrap();
+            assert_eq!(next.possibilities, possibilities.len());
+            assert_eq!(next.winnable, possibilities.len(), "{}", strategy);
+            for possibility in &possibilities {
+                let next_state = possibility.apply(&action).unwrap();
+                let left = hint(&next_state, &config).unwrap().moves_left;
+                assert!(left < next.worst_moves_left);
+            }
+        }
+        let robust = one_move_hidden_hint(&state, HiddenStrategy::Robust, &config).unwrap();
+        let informative =
+            one_move_hidden_hint(&state, HiddenStrategy::Informative, &config).unwrap();
+        assert!(robust.worst_moves_left <= informative.worst_moves_left);
+        assert!(informative.outcomes >= robust.outcomes);
+    }
+
+    fn tops_only(capacity: usize, tops: &[LiquidColor], empty_count: usize) -> TubeState {
+        let mut tubes: Vec<Tube> = tops
+            .iter()
+            .map(|top| {
+                let mut layers = vec![LiquidColor::Unknown; capacity];
+                layers[0] = top.clone();
+                return Tube::from_vec_with_capacity(layers, capacity);
+            })
+            .collect();
+        tubes.resize(tubes.len() + empty_count, Tube::empty(capacity));
+        return TubeState { tubes };
+    }
+
+    #[test]
+    fn test_colors_that_are_never_seen_fill_the_rest() {
+        let (red, blue, green) = (LiquidColor::Red, LiquidColor::Blue, LiquidColor::Green);
+        let state = tops_only(4, &[red.clone(), red.clone(), blue.clone(), green], 2);
+        let colors = HiddenColors::of(&state).unwrap();
+        assert_eq!(colors.unseen, 1);
+        // 12! / (2! 3! 3! 4!) ways to fill in two red, three blue, three green and four of
+        // a color that can't be seen
+        assert_eq!(colors.fillings(), 277_200);
+        assert_eq!(
+            hidden_possibilities(&state),
+            Err(SolveError::SearchLimit(Limit::Possibilities(
+                MAX_POSSIBILITIES
+            )))
+        );
+
+        let state = tops_only(3, &[red.clone(), red, blue], 2);
+        let possibilities = hidden_possibilities(&state).unwrap();
+        assert_eq!(possibilities.len(), 60);
+        assert_eq!(HiddenColors::of(&state).unwrap().fillings(), 60);
+        let distinct: HashSet<&TubeState> = possibilities.iter().collect();
+        assert_eq!(distinct.len(), possibilities.len());
+        for possibility in &possibilities {
+            assert!(possibility.validate().is_ok());
+        }
+
+        // Two colors that can't be seen anywhere can be swapped, so that is only counted
+        // once: one orange and two each of two unseen colors make 5! / (2! 2!) / 2!
+        let mut state = tops_only(2, &[LiquidColor::Orange], 3);
+        state.tubes[1] = Tube::from_vec_with_capacity(vec![LiquidColor::Unknown; 2], 2);
+        state.tubes[2] = Tube::from_vec_with_capacity(vec![LiquidColor::Unknown; 2], 2);
+        assert_eq!(hidden_possibilities(&state).unwrap().len(), 15);
+    }
+
+    #[test]
+    fn test_safe_hint_solves_tops_only_for_every_filling() {
+        let state = tops_only(
+            3,
+            &[LiquidColor::Red, LiquidColor::Red, LiquidColor::Blue],
+            2,
+        );
+        let config = SearchConfig::default();
+        let first = safe_hidden_hint(&state, &config).unwrap();
+        assert!(first.action.is_some());
+        assert_eq!(first.possibilities, 60);
+        // Follow the hints against each filling, seeing only what the moves reveal. Many
+        // fillings look the same for a while, so each hint is only worked out once.
+        let mut hints: HashMap<TubeState, Option<TransferAction>> = HashMap::new();
+        for possibility in hidden_possibilities(&state).unwrap() {
+            let mut actual = possibility;
+            let mut seen = state.clone();
+            let mut moves = 0;
+            while let Some(action) = *hints
+                .entry(seen.clone())
+                .or_insert_with(|| return safe_hidden_hint(&seen, &config).unwrap().action)
+            {
+                actual = actual.apply(&action).unwrap();
+                seen = observe(&seen, &actual);
+                moves += 1;
+                assert!(moves <= first.worst_moves_left);
+            }
+            assert!(actual.issolved());
+        }
+    }
+
+    #[test]
+    fn test_safe_hint_needs_at_least_as_many_moves_as_knowing_the_colors() {
+        let state = load_level("levels/hidden.toml");
+        let config = SearchConfig::default();
+        let safe = one_move_hidden_hint(&state, HiddenStrategy::Safe, &config).unwrap();
+        let robust = one_move_hidden_hint(&state, HiddenStrategy::Robust, &config).unwrap();
+        assert_eq!(safe.winnable, safe.possibilities);
+        assert_eq!(safe.possibilities, robust.possibilities);
+        assert!(safe.worst_moves_left >= robust.worst_moves_left);
+    }
+
+    #[test]
+    fn test_without_hidden_layers_hints_a_shortest_solution() {
+        let state = load_level("levels/3.toml");
+        let config = SearchConfig::default();
+        let next = one_move_hidden_hint(&state, HiddenStrategy::Robust, &config).unwrap();
+        assert_eq!(next.possibilities, 1);
+        assert_eq!(next.outcomes, 1);
+        assert_eq!(
+            next.worst_moves_left,
+            hint(&state, &config).unwrap().moves_left
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use crate::{AStar, SearchConfig, SolveError, Solver, TransferAction, TubeState};
+
+/// The next move to make from some state of a game
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Hint {
+    /// The first move of a solution with the fewest moves, or `None` if the state is
+    /// already solved
+    pub action: Option<TransferAction>,
+    /// How many moves the solution takes, including `action`
+    pub moves_left: usize,
+}
+
+/// Returns the next move of a solution with the fewest moves from `state`
+///
+/// `state` can be anywhere in a game, not just the start of a level. If no sequence of
+/// moves solves it any more, the error is `SolveError::Unsolvable`.
+///
+/// ```
+/// use tubes::{hint, LiquidColor, SearchConfig, SolveError, Tube, TubeArray, TubeState};
+/// let state = TubeState {
+///     tubes: vec![
+///         Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Orange]),
+///         Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Orange]),
+///     ],
+/// };
+/// let next = hint(&state, &SearchConfig::default()).unwrap();
+/// assert_eq!(next.moves_left, 1);
+///
+/// // Level 2 can't be solved without its empty tube
+/// let contents = std::fs::read_to_string("levels/2.toml").unwrap();
+/// let mut dead_end = TubeState::from_tube_array(toml::from_str::<TubeArray>(&contents).unwrap());
+/// dead_end.tubes.pop();
+/// assert_eq!(hint(&dead_end, &SearchConfig::default()), Err(SolveError::Unsolvable));
+/// ```
+pub fn hint(state: &TubeState, config: &SearchConfig) -> Result<Hint, SolveError> {
+    let solution = AStar.solve(state, config).result?;
+    return Ok(Hint {
+        action: solution.actions.first().copied(),
+        moves_left: solution.actions.len(),
+    });
+}
+
+#[cfg(test)]
+mod hint_tests {
+    use super::*;
+    use crate::{load_level, replay, solve_bfs};
+
+    #[test]
+    fn test_hints_follow_a_shortest_solution() {
+        let initial_state = load_level("levels/3.toml");
+        let solution = solve_bfs(&initial_state).unwrap();
+        let config = SearchConfig::default();
+        // Start from partway through a game rather than the start of the level
+        let mut state = replay(&initial_state, &solution.actions[..2])
+            .unwrap()
+            .pop()
+            .unwrap();
+        let mut moves_left = solution.actions.len() - 2;
+        while moves_left > 0 {
+            let next = hint(&state, &config).unwrap();
+            assert_eq!(next.moves_left, moves_left);
+            state = state.apply(&next.action.unwrap()).unwrap();
+            moves_left -= 1;
+        }
+        assert!(state.issolved());
+        assert_eq!(
+            hint(&state, &config),
+            Ok(Hint {
+                action: None,
+                moves_left: 0
+            })
+        );
+    }
+}
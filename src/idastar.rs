@@ -0,0 +1,130 @@
+use crate::search::SearchContext;
+use crate::{
+    lower_bound, SearchConfig, SearchReport, SolveError, Solver, TransferAction, TubeState,
+    TubeStateNode,
+};
+
+/// Iterative deepening A* search
+///
+/// Runs a depth first search that cuts off any line of play whose moves so far plus
+/// `lower_bound` exceed a threshold, then raises the threshold to the smallest estimate
+/// that was cut off and tries again. Only the states on the current line of play are kept
+/// in memory, so memory use grows with the length of the solution instead of with the
+/// number of states explored. The bound never overestimates, so the first solution found
+/// has the fewest possible transfers.
+///
+/// Because there is no explored set the same state can be expanded many times, once for
+/// every order of moves that reaches it, so this is slower than `AStar` on levels that
+/// fit in memory.
+pub struct IdaStar;
+
+impl Solver for IdaStar {
+    fn name(&self) -> &str {
+        return "idastar";
+    }
+
+    fn solve(&self, initial_state: &TubeState, config: &SearchConfig) -> SearchReport {
+        let mut search = SearchContext::new(config);
+        let result = idastar(initial_state, &mut search);
+        return search.finish(result);
+    }
+}
+
+enum Bound {
+    Found,
+    Exceeded(i32),
+    Exhausted,
+}
+
+fn idastar(
+    initial_state: &TubeState,
+    search: &mut SearchContext,
+) -> Result<TubeStateNode, SolveError> {
+    initial_state.validate()?;
+    let mut threshold = lower_bound(initial_state);
+    let mut path = vec![initial_state.canonical()];
+    let mut actions = Vec::new();
+    let mut state = initial_state.clone();
+    loop {
+        match bounded_dfs(&mut state, &mut path, &mut actions, threshold, search)? {
+            Bound::Found => return Ok(TubeStateNode { actions, state }),
+            Bound::Exceeded(next_threshold) => threshold = next_threshold,
+            Bound::Exhausted => return Err(SolveError::Unsolvable),
+        }
+    }
+}
+
+/// Searches below `state` for a solution within `threshold` transfers
+///
+/// `path` holds the canonical form of every state on the current line of play so the
+/// search never walks in a circle. On success `state` and `actions` are left holding the
+/// solved state and the moves that reach it.
+fn bounded_dfs(
+    state: &mut TubeState,
+    path: &mut Vec<TubeState>,
+    actions: &mut Vec<TransferAction>,
+    threshold: i32,
+    search: &mut SearchContext,
+) -> Result<Bound, SolveError> {
+    let estimate = actions.len() as i32 + lower_bound(state);
+    if estimate > threshold {
+        return Ok(Bound::Exceeded(estimate));
+    }
+    if state.issolved() {
+        return Ok(Bound::Found);
+    }
+    search.expand(actions.len())?;
+    search.frontier(path.len());
+    let mut next_threshold = None;
+    for (action, mut next_state) in search.successors(state, actions.last()) {
+        search.generated();
+        let canonical = next_state.canonical();
+        if path.contains(&canonical) {
+            search.duplicate();
+            continue;
+        }
+        path.push(canonical);
+        actions.push(action);
+        match bounded_dfs(&mut next_state, path, actions, threshold, search)? {
+            Bound::Found => {
+                *state = next_state;
+                return Ok(Bound::Found);
+            }
+            Bound::Exceeded(exceeded) => {
+                next_threshold = Some(next_threshold.map_or(exceeded, |t: i32| t.min(exceeded)));
+            }
+            Bound::Exhausted => {}
+        }
+        path.pop();
+        actions.pop();
+    }
+    match next_threshold {
+        Some(next_threshold) => return Ok(Bound::Exceeded(next_threshold)),
+        None => return Ok(Bound::Exhausted),
+    }
+}
+
+#[cfg(test)]
+mod idastar_tests {
+    use super::*;
+    use crate::load_level;
+
+    #[test]
+    fn test_idastar_memory_is_linear_in_depth() {
+        let state = load_level("levels/2.toml");
+        let report = IdaStar.solve(&state, &SearchConfig::default());
+        let solution = report.result.unwrap();
+        assert_eq!(solution.actions.len(), 7);
+        assert!(solution.state.issolved());
+        assert!(report.stats.peak_frontier <= solution.actions.len() + 1);
+    }
+
+    #[test]
+    fn test_idastar_unsolvable() {
+        // Level 2 without its empty tube
+        let mut state = load_level("levels/2.toml");
+        state.tubes.pop();
+        let report = IdaStar.solve(&state, &SearchConfig::default());
+        assert_eq!(report.result.err(), Some(SolveError::Unsolvable));
+    }
+}
@@ -1,9 +1,71 @@
+#![allow(clippy::needless_return)]
+// Older code that these lints would flag is left as it was written
+#![allow(
+    clippy::redundant_field_names,
+    clippy::explicit_counter_loop,
+    clippy::needless_borrow,
+    clippy::bool_assert_comparison
+)]
+
 use colored::*;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
 
-#[derive(Debug, PartialEq, Clone)]
+mod anytime;
+mod astar;
+mod beam;
+mod bidirectional;
+mod compact;
+mod cost;
+mod dfs;
+mod enumerate;
+mod external;
+mod hidden;
+mod hint;
+mod idastar;
+mod palette;
+mod parallel;
+mod pruning;
+mod replay;
+mod review;
+mod rules;
+mod search;
+
+pub use anytime::Anytime;
+pub use astar::{
+    lower_bound, solve_astar, solve_astar_with_config, AStar, UniformCost, WeightedAStar,
+};
+pub use beam::{BeamSearch, GreedyBestFirst, Heuristic, LowerBound, DEFAULT_BEAM_WIDTH};
+pub use bidirectional::{reverse_neighbors, Bidirectional};
+pub use compact::{CompactLayout, CompactState};
+use compact::{CompactNode, MoveTree};
+pub use cost::{CostModel, EMPTY_TUBE_PENALTY};
+pub use dfs::Dfs;
+pub use enumerate::{
+    count_shortest_solutions, group_commuting_solutions, k_shortest_solutions, shortest_solutions,
+    shortest_solutions_with_count,
+};
+pub use external::{ExternalBfs, DEFAULT_STATES_IN_MEMORY};
+pub use hidden::{
+    hidden_possibilities, one_move_hidden_hint, safe_hidden_hint, HiddenHint, HiddenStrategy,
+    MAX_POSSIBILITIES,
+};
+pub use hint::{hint, Hint};
+pub use idastar::IdaStar;
+pub use palette::{ColorId, Palette};
+pub use parallel::ParallelBfs;
+pub use pruning::pruned_neighbors;
+pub use replay::{parse_moves, replay, replay_with_rules, MoveError, ReplayError};
+pub use review::{review_moves, Review, ReviewError};
+pub use rules::Rules;
+use search::SearchContext;
+pub use search::{
+    Algorithm, Limit, ProgressCallback, SearchConfig, SearchReport, SearchStats, SolveError,
+    Solver, PROGRESS_INTERVAL,
+};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub enum LiquidColor {
     Empty,
     Orange,
@@ -11,54 +73,44 @@ pub enum LiquidColor {
     Red,
     Pink,
     Green,
-    Other(String),
-}
-
-impl serde::Serialize for LiquidColor {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        serializer.serialize_str(match *self {
-            LiquidColor::Empty => "empty",
-            LiquidColor::Orange => "orange",
-            LiquidColor::Blue => "blue",
-            LiquidColor::Red => "red",
-            LiquidColor::Pink => "pink",
-            LiquidColor::Green => "green",
-            LiquidColor::Other(ref other) => other,
-        })
-    }
+    /// Any other color, named in the level's `Palette`
+    Other(ColorId),
+    /// A layer the game hides behind a "?" until the liquid above it is poured away
+    Unknown,
 }
 
-impl<'de> serde::Deserialize<'de> for LiquidColor {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let s = String::deserialize(deserializer)?;
-        Ok(match s.as_str() {
-            "empty" => LiquidColor::Empty,
-            "orange" => LiquidColor::Orange,
-            "blue" => LiquidColor::Blue,
-            "red" => LiquidColor::Red,
-            "pink" => LiquidColor::Pink,
-            "green" => LiquidColor::Green,
-            _ => LiquidColor::Other(s),
-        })
+impl fmt::Display for LiquidColor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LiquidColor::Empty => write!(f, "empty"),
+            LiquidColor::Orange => write!(f, "orange"),
+            LiquidColor::Blue => write!(f, "blue"),
+            LiquidColor::Red => write!(f, "red"),
+            LiquidColor::Pink => write!(f, "pink"),
+            LiquidColor::Green => write!(f, "green"),
+            LiquidColor::Other(other) => write!(f, "{}", other),
+            LiquidColor::Unknown => write!(f, "?"),
+        }
     }
 }
 
 impl LiquidColor {
-    pub fn new(r: u8, g: u8, b: u8) -> LiquidColor {
-        if r == g && g == b {
-            return LiquidColor::Empty;
-        } else {
-            return LiquidColor::Other(hex::encode([r, g, b]));
-        }
+    /// Returns the color a level file means by `name` if it isn't a palette color: one of
+    /// the named colors, `empty`, or `?` for a hidden layer
+    pub fn from_name(name: &str) -> Option<LiquidColor> {
+        return match name {
+            "empty" => Some(LiquidColor::Empty),
+            "orange" => Some(LiquidColor::Orange),
+            "blue" => Some(LiquidColor::Blue),
+            "red" => Some(LiquidColor::Red),
+            "pink" => Some(LiquidColor::Pink),
+            "green" => Some(LiquidColor::Green),
+            "?" => Some(LiquidColor::Unknown),
+            _ => None,
+        };
     }
 
-    fn to_colored_color(&self) -> Color {
+    fn to_colored_color(&self, palette: &Palette) -> Color {
         match &self {
             LiquidColor::Empty => {
                 return Color::Black;
@@ -98,9 +150,15 @@ impl LiquidColor {
                     b: 0x7c,
                 };
             }
-            &LiquidColor::Other(hex_color) => {
-                let mut rgb = [0u8; 3];
-                let _ = hex::decode_to_slice(hex_color, &mut rgb as &mut [u8]);
+            LiquidColor::Unknown => {
+                return Color::TrueColor {
+                    r: 0x80,
+                    g: 0x80,
+                    b: 0x80,
+                };
+            }
+            LiquidColor::Other(id) => {
+                let rgb = palette.rgb(*id).unwrap_or([0; 3]);
                 return Color::TrueColor {
                     r: rgb[0],
                     g: rgb[1],
@@ -111,9 +169,13 @@ impl LiquidColor {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+/// How many layers of liquid a tube holds when a level doesn't say
+pub const DEFAULT_CAPACITY: usize = 4;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub struct Tube {
-    pub layers: [LiquidColor; 4],
+    /// The layers of liquid from the top of the tube down, one per unit of capacity
+    pub layers: Vec<LiquidColor>,
 }
 
 impl Tube {
@@ -128,9 +190,29 @@ impl Tube {
     /// assert_eq!(half_tube.isempty(), false);
     /// ```
     pub fn isempty(&self) -> bool {
-        return 4 == self.howempty();
+        return self.layers.len() as i32 == self.howempty();
     }
 
+    /// Returns the number of layers of liquid the tube can hold
+    pub fn capacity(&self) -> usize {
+        return self.layers.len();
+    }
+
+    /// Creates a tube with nothing in it that can hold `capacity` layers
+    ///
+    /// ```
+    /// use tubes::Tube;
+    /// let tube = Tube::empty(6);
+    /// assert!(tube.isempty());
+    /// assert_eq!(tube.capacity(), 6);
+    /// ```
+    pub fn empty(capacity: usize) -> Tube {
+        return Tube {
+            layers: vec![LiquidColor::Empty; capacity],
+        };
+    }
+
+    /// Creates a tube that holds four layers, listed from the top down
     pub fn new(
         layer3: LiquidColor,
         layer2: LiquidColor,
@@ -138,7 +220,7 @@ impl Tube {
         layer0: LiquidColor,
     ) -> Tube {
         let new_tube = Tube {
-            layers: [layer3, layer2, layer1, layer0],
+            layers: vec![layer3, layer2, layer1, layer0],
         };
         if !new_tube.checkrep() {
             panic!("invalid tube");
@@ -202,15 +284,35 @@ impl Tube {
         return empty_count;
     }
 
+    /// Returns true if the tube holds liquid of only one color
+    ///
+    /// ```
+    /// use tubes::LiquidColor;
+    /// use tubes::Tube;
+    /// assert!(Tube::from_vec(vec![LiquidColor::Blue, LiquidColor::Blue]).ismonochrome());
+    /// assert!(!Tube::from_vec(vec![LiquidColor::Blue, LiquidColor::Red]).ismonochrome());
+    /// assert!(!Tube::from_vec(vec![]).ismonochrome());
+    /// ```
+    pub fn ismonochrome(&self) -> bool {
+        let (color_count, _top_color) = self.topcolor();
+        return color_count > 0 && color_count + self.howempty() == self.layers.len() as i32;
+    }
+
     pub fn issolved(&self) -> bool {
         let empty_count = self.howempty();
         let (color_count, _top_color) = self.topcolor();
-        return empty_count == 4 || color_count == 4;
+        let capacity = self.layers.len() as i32;
+        return empty_count == capacity || color_count == capacity;
     }
 
     pub fn pourtube(&self) -> Tube {
-        let mut new_tube = self.clone();
         let (color_count, _color) = self.topcolor();
+        return self.pourlayers(color_count);
+    }
+
+    /// Removes the top `color_count` layers of liquid from the tube
+    pub fn pourlayers(&self, color_count: i32) -> Tube {
+        let mut new_tube = self.clone();
         let empty_count = self.howempty();
         for i in empty_count..empty_count + color_count {
             new_tube.layers[i as usize] = LiquidColor::Empty;
@@ -222,7 +324,7 @@ impl Tube {
         let empty_count = self.howempty();
         // Fail because recieve tube doesn't have enough empty space
         if color_count > empty_count {
-            return (false, EMPTY_TUBE);
+            return (false, Tube::empty(self.capacity()));
         }
         let mut new_tube = self.clone();
         for i in empty_count - color_count..empty_count {
@@ -249,36 +351,42 @@ impl Tube {
     /// assert_eq!(actual_short.topcolor().1, LiquidColor::Orange);
     /// ```
     pub fn from_vec(vec: Vec<LiquidColor>) -> Tube {
+        return Tube::from_vec_with_capacity(vec, DEFAULT_CAPACITY);
+    }
+
+    /// Creates a tube that holds `capacity` layers from a Vec, filling the space above the
+    /// liquid with empty layers
+    ///
+    /// ```
+    /// use tubes::LiquidColor;
+    /// use tubes::Tube;
+    /// let tube = Tube::from_vec_with_capacity(vec![LiquidColor::Blue, LiquidColor::Red], 3);
+    /// assert_eq!(tube.layers, vec![LiquidColor::Empty, LiquidColor::Blue, LiquidColor::Red]);
+    /// assert!(!tube.issolved());
+    /// ```
+    pub fn from_vec_with_capacity(vec: Vec<LiquidColor>, capacity: usize) -> Tube {
         assert!(
-            vec.len() <= 4,
-            "Too many colors to create a valid tube! You provided {} colors when the max is 4.",
-            vec.len()
+            vec.len() <= capacity,
+            "Too many colors to create a valid tube! You provided {} colors when the max is {}.",
+            vec.len(),
+            capacity
         );
         let mut new_vec = vec.clone();
         new_vec.reverse();
-        new_vec.resize(4, LiquidColor::Empty);
-        return Tube::new(
-            new_vec[3].clone(),
-            new_vec[2].clone(),
-            new_vec[1].clone(),
-            new_vec[0].clone(),
-        );
+        new_vec.resize(capacity, LiquidColor::Empty);
+        new_vec.reverse();
+        let new_tube = Tube { layers: new_vec };
+        if !new_tube.checkrep() {
+            panic!("invalid tube");
+        }
+        return new_tube;
     }
 
     pub fn to_vec(&self) -> Vec<LiquidColor> {
-        return Vec::from_iter(self.layers.clone());
+        return self.layers.clone();
     }
 }
 
-pub const EMPTY_TUBE: Tube = Tube {
-    layers: [
-        LiquidColor::Empty,
-        LiquidColor::Empty,
-        LiquidColor::Empty,
-        LiquidColor::Empty,
-    ],
-};
-
 struct TubeTransferResult {
     success: bool,
     send_tube: Tube,
@@ -292,40 +400,56 @@ struct TubeTransferResult {
 /// Success flag in TubeTransferResult will be true and a copy of the send and recieve
 /// tubes will be the next elements repectively. If the transfer fails the Success flag
 /// will be false and no guarentees are made to the content of the Tubes
-fn transfer(send: Tube, recv: Tube) -> TubeTransferResult {
+fn transfer(send: Tube, recv: Tube, rules: Rules) -> TubeTransferResult {
+    match try_transfer(&send, &recv, rules) {
+        Ok((new_send, new_recv)) => {
+            return TubeTransferResult {
+                success: true,
+                send_tube: new_send,
+                recieve_tube: new_recv,
+            };
+        }
+        Err(_) => {
+            return TubeTransferResult {
+                success: false,
+                send_tube: send,
+                recieve_tube: recv,
+            };
+        }
+    }
+}
+
+/// Transfers liquid like `transfer`, returning the new send and recieve tubes or the
+/// reason the rules of the game don't allow the transfer
+fn try_transfer(send: &Tube, recv: &Tube, rules: Rules) -> Result<(Tube, Tube), MoveError> {
     // Fail because send tube is empty
     if send.isempty() {
-        return TubeTransferResult {
-            success: false,
-            send_tube: send,
-            recieve_tube: recv,
-        };
+        return Err(MoveError::EmptySource);
     }
     let (send_color_count, send_color) = send.topcolor();
     let (_recv_color_count, recv_color) = recv.topcolor();
     // Fail because cannot pour onto different color
     if recv_color != send_color && recv_color != LiquidColor::Empty {
-        return TubeTransferResult {
-            success: false,
-            send_tube: send,
-            recieve_tube: recv,
-        };
+        return Err(MoveError::ColorMismatch {
+            poured: send_color,
+            top: recv_color,
+        });
     }
-    let new_send = send.pourtube();
-    let (recv_success, new_recv) = recv.addcolor(send_color_count, send_color);
+    // Under partial pours only as much as fits is poured
+    let poured_count = match rules {
+        Rules::WholeBlock => send_color_count,
+        Rules::PartialPour => send_color_count.min(recv.howempty()),
+    };
+    let new_send = send.pourlayers(poured_count);
+    let (recv_success, new_recv) = recv.addcolor(poured_count, send_color);
     // Fail because addcolor failed
-    if !recv_success {
-        return TubeTransferResult {
-            success: false,
-            send_tube: send,
-            recieve_tube: recv,
-        };
+    if !recv_success || poured_count == 0 {
+        return Err(MoveError::NotEnoughRoom {
+            poured: send_color_count,
+            room: recv.howempty(),
+        });
     }
-    return TubeTransferResult {
-        success: true,
-        send_tube: new_send,
-        recieve_tube: new_recv,
-    };
+    return Ok((new_send, new_recv));
 }
 
 #[cfg(test)]
@@ -346,7 +470,7 @@ mod transfer_tests {
             LiquidColor::Empty,
             LiquidColor::Empty,
         );
-        let transfer_result = transfer(tube1, tube2);
+        let transfer_result = transfer(tube1, tube2, Rules::WholeBlock);
         let transfer_success = transfer_result.success;
         assert_eq!(transfer_success, false);
     }
@@ -365,7 +489,7 @@ mod transfer_tests {
             LiquidColor::Empty,
             LiquidColor::Empty,
         );
-        let transfer_result = transfer(tube1.clone(), tube2.clone());
+        let transfer_result = transfer(tube1.clone(), tube2.clone(), Rules::WholeBlock);
         assert_eq!(transfer_result.success, true);
         assert_eq!(transfer_result.send_tube, tube2);
         assert_eq!(transfer_result.recieve_tube, tube1);
@@ -385,7 +509,7 @@ mod transfer_tests {
             LiquidColor::Orange,
             LiquidColor::Orange,
         );
-        let transfer_result = transfer(tube1, tube2);
+        let transfer_result = transfer(tube1, tube2, Rules::WholeBlock);
         assert_eq!(transfer_result.success, false);
     }
 
@@ -403,12 +527,46 @@ mod transfer_tests {
             LiquidColor::Blue,
             LiquidColor::Blue,
         );
-        let transfer_result = transfer(tube1, tube2);
+        let transfer_result = transfer(tube1, tube2, Rules::WholeBlock);
         assert_eq!(transfer_result.success, false);
     }
+
+    #[test]
+    fn test_partial_transfer() {
+        let tube1 = Tube::new(
+            LiquidColor::Empty,
+            LiquidColor::Orange,
+            LiquidColor::Orange,
+            LiquidColor::Blue,
+        );
+        let tube2 = Tube::new(
+            LiquidColor::Empty,
+            LiquidColor::Orange,
+            LiquidColor::Blue,
+            LiquidColor::Blue,
+        );
+        let whole_result = transfer(tube1.clone(), tube2.clone(), Rules::WholeBlock);
+        assert!(!whole_result.success);
+        let transfer_result = transfer(tube1, tube2, Rules::PartialPour);
+        assert!(transfer_result.success);
+        let expected_send = Tube::new(
+            LiquidColor::Empty,
+            LiquidColor::Empty,
+            LiquidColor::Orange,
+            LiquidColor::Blue,
+        );
+        let expected_recv = Tube::new(
+            LiquidColor::Orange,
+            LiquidColor::Orange,
+            LiquidColor::Blue,
+            LiquidColor::Blue,
+        );
+        assert_eq!(transfer_result.send_tube, expected_send);
+        assert_eq!(transfer_result.recieve_tube, expected_recv);
+    }
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 pub struct TransferAction {
     send_idx: i32,
     recv_idx: i32,
@@ -416,16 +574,111 @@ pub struct TransferAction {
 
 impl fmt::Display for TransferAction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The alternate form is short enough to list a whole solution on one line
+        if f.alternate() {
+            return write!(f, "{}->{}", self.send_idx, self.recv_idx);
+        }
         write!(f, "pour tube {} into tube {}", self.send_idx, self.recv_idx)
     }
 }
 
+/// Returns the smallest tube capacity that fits the liquid in the given tubes
+///
+/// Each tube lists its liquid without any empty layers. Every color has to fill a whole
+/// number of tubes, so the capacity divides the number of layers of each color, and it
+/// has to be at least as tall as the fullest tube. Returns `None` if there is no liquid
+/// or no capacity fits.
+///
+/// Hidden layers could be any color, so when there are some the fullest tube is taken to
+/// be full, which it is at the start of a level.
+///
+/// ```
+/// use tubes::{infer_capacity, LiquidColor};
+/// let blue = LiquidColor::Blue;
+/// let red = LiquidColor::Red;
+/// let tubes = vec![
+///     vec![blue.clone(), red.clone(), blue.clone()],
+///     vec![red.clone(), blue.clone(), red.clone()],
+///     vec![],
+/// ];
+/// assert_eq!(infer_capacity(&tubes), Some(3));
+/// // Part way through a game no tube has to be full
+/// let tubes = vec![vec![blue.clone(), blue.clone()], vec![blue.clone(), blue], vec![red.clone(); 3], vec![red]];
+/// assert_eq!(infer_capacity(&tubes), Some(4));
+/// let hidden = LiquidColor::Unknown;
+/// let orange = LiquidColor::Orange;
+/// let tubes = vec![vec![orange.clone(), hidden.clone(), hidden.clone()], vec![orange, hidden.clone(), hidden], vec![]];
+/// assert_eq!(infer_capacity(&tubes), Some(3));
+/// ```
+pub fn infer_capacity(tubes: &[Vec<LiquidColor>]) -> Option<usize> {
+    let mut color_counts: HashMap<&LiquidColor, usize> = HashMap::new();
+    for layer in tubes.iter().flatten() {
+        if layer != &LiquidColor::Empty {
+            *color_counts.entry(layer).or_insert(0) += 1;
+        }
+    }
+    let tallest = tubes.iter().map(|t| t.len()).max().unwrap_or(0).max(1);
+    if let Some(hidden_count) = color_counts.remove(&LiquidColor::Unknown) {
+        let layer_count = hidden_count + color_counts.values().sum::<usize>();
+        if layer_count % tallest == 0 {
+            return Some(tallest);
+        }
+        return None;
+    }
+    let divisor = color_counts.values().fold(0, |a, b| {
+        let (mut a, mut b) = (a, *b);
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        return a;
+    });
+    return (tallest..=divisor).find(|capacity| divisor % capacity == 0);
+}
+
+/// The contents of a level file
+///
+/// `capacity` is how many layers every tube holds, which is `DEFAULT_CAPACITY` if the
+/// file doesn't say. Levels with tubes of different sizes list the capacity of each tube
+/// in `capacities` instead, and any tube missing from that list holds `capacity`.
+///
+/// Colors are kept by name until the level is turned into a `TubeState`, which numbers
+/// them with the level's `palette`.
+///
+/// ```
+/// use tubes::{TubeArray, TubeState};
+/// let tube_array: TubeArray = toml::from_str(
+///     "capacity = 3\ntubes = [['blue', 'red', 'red'], ['red', 'blue', 'blue'], []]",
+/// )
+/// .unwrap();
+/// let state = TubeState::from_tube_array(tube_array);
+/// assert_eq!(state.tubes[2].capacity(), 3);
+/// assert!(state.validate().is_ok());
+///
+/// let tube_array: TubeArray = toml::from_str(
+///     "capacities = [6, 3, 3]\ntubes = [['blue', 'blue', 'blue', 'red', 'red', 'red'], [], []]",
+/// )
+/// .unwrap();
+/// let state = TubeState::from_tube_array(tube_array);
+/// assert_eq!(state.tubes[0].capacity(), 6);
+/// assert_eq!(state.tubes[1].capacity(), 3);
+/// ```
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct TubeArray {
-    tubes: Vec<Vec<LiquidColor>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    capacity: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    capacities: Option<Vec<usize>>,
+    tubes: Vec<Vec<String>>,
+}
+
+impl TubeArray {
+    /// Returns the palette for the colors of the level that aren't named colors
+    pub fn palette(&self) -> Palette {
+        return Palette::from_names(self.tubes.iter().flatten().map(|name| name.as_str()));
+    }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct TubeState {
     pub tubes: Vec<Tube>,
 }
@@ -440,47 +693,352 @@ impl TubeState {
         return true;
     }
 
+    /// Returns the state with its tubes sorted into a canonical order
+    ///
+    /// The order of the tubes doesn't change which moves are possible, so two states that
+    /// only differ in the order of their tubes have the same canonical form. The solvers use
+    /// this to avoid exploring the same puzzle once for every ordering of its tubes, while
+    /// still reporting moves with the tube indices of the states they actually walk through.
+    ///
+    /// ```
+    /// use tubes::LiquidColor;
+    /// use tubes::Tube;
+    /// use tubes::TubeState;
+    /// let orange = Tube::from_vec(vec![LiquidColor::Orange]);
+    /// let blue = Tube::from_vec(vec![LiquidColor::Blue]);
+    /// let state1 = TubeState { tubes: vec![orange.clone(), blue.clone()] };
+    /// let state2 = TubeState { tubes: vec![blue, orange] };
+    /// assert_ne!(state1, state2);
+    /// assert_eq!(state1.canonical(), state2.canonical());
+    /// ```
+    pub fn canonical(&self) -> TubeState {
+        let mut tubes = self.tubes.clone();
+        tubes.sort();
+        return TubeState { tubes };
+    }
+
+    /// Checks that the state could ever be solved by the rules of the game
+    ///
+    /// A state is rejected if it has no tubes, if a tube has empty space below its liquid,
+    /// or if the layers of some color can't exactly fill a whole number of tubes. Hidden
+    /// layers are rejected too, since the solvers need to know every color; see
+    /// `one_move_hidden_hint` for states with hidden layers.
+    ///
+    /// ```
+    /// use tubes::LiquidColor;
+    /// use tubes::SolveError;
+    /// use tubes::Tube;
+    /// use tubes::TubeState;
+    /// let state = TubeState {
+    ///     tubes: vec![
+    ///         Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Orange, LiquidColor::Orange]),
+    ///         Tube::from_vec(vec![]),
+    ///     ],
+    /// };
+    /// assert!(matches!(state.validate(), Err(SolveError::InvalidInput(_))));
+    /// ```
+    pub fn validate(&self) -> Result<(), SolveError> {
+        if self.tubes.is_empty() {
+            return Err(SolveError::InvalidInput(String::from("there are no tubes")));
+        }
+        let mut color_counts: HashMap<&LiquidColor, usize> = HashMap::new();
+        for (ti, tube) in self.tubes.iter().enumerate() {
+            let empty_count = tube.howempty() as usize;
+            for layer in &tube.layers[empty_count..] {
+                if layer == &LiquidColor::Empty {
+                    return Err(SolveError::InvalidInput(format!(
+                        "tube {} has empty space below its liquid",
+                        ti
+                    )));
+                }
+                if layer == &LiquidColor::Unknown {
+                    return Err(SolveError::InvalidInput(format!(
+                        "tube {} has hidden layers",
+                        ti
+                    )));
+                }
+                *color_counts.entry(layer).or_insert(0) += 1;
+            }
+        }
+        for (ti, tube) in self.tubes.iter().enumerate() {
+            if tube.capacity() == 0 {
+                return Err(SolveError::InvalidInput(format!(
+                    "tube {} can't hold any liquid",
+                    ti
+                )));
+            }
+        }
+        let capacity = self.tubes[0].capacity();
+        if self.tubes.iter().any(|t| t.capacity() != capacity) {
+            if self.solved_states().is_empty() {
+                return Err(SolveError::InvalidInput(String::from(
+                    "the liquid can't be sorted so that every tube is full of one color or empty",
+                )));
+            }
+            return Ok(());
+        }
+        let mut colors: Vec<_> = color_counts.into_iter().collect();
+        colors.sort();
+        for (color, count) in colors {
+            if count % capacity != 0 {
+                return Err(SolveError::InvalidInput(format!(
+                    "there are {} layers of {} but it takes {} to fill a tube",
+                    count, color, capacity
+                )));
+            }
+        }
+        return Ok(());
+    }
+
+    /// Returns every way the liquid in the state could end up sorted
+    ///
+    /// In a solved state every tube is full of one color or empty. States that only
+    /// differ in the order of their tubes are only returned once. When all of the tubes
+    /// hold the same amount there is just one way to sort the liquid, but with tubes of
+    /// different sizes a color can often fill either one tall tube or a few short ones.
+    ///
+    /// ```
+    /// use tubes::{LiquidColor, Tube, TubeState};
+    /// let state = TubeState {
+    ///     tubes: vec![
+    ///         Tube::from_vec_with_capacity(vec![LiquidColor::Blue, LiquidColor::Red], 2),
+    ///         Tube::from_vec_with_capacity(vec![LiquidColor::Red, LiquidColor::Blue], 2),
+    ///         Tube::from_vec_with_capacity(vec![LiquidColor::Blue, LiquidColor::Blue], 2),
+    ///         Tube::from_vec_with_capacity(vec![], 4),
+    ///     ],
+    /// };
+    /// // The blue can fill the tall tube or two of the short ones
+    /// assert_eq!(state.solved_states().len(), 2);
+    /// assert!(state.solved_states().iter().all(|s| s.issolved()));
+    /// ```
+    pub fn solved_states(&self) -> Vec<TubeState> {
+        let mut color_counts: HashMap<&LiquidColor, usize> = HashMap::new();
+        for layer in self.tubes.iter().flat_map(|t| t.layers.iter()) {
+            if layer != &LiquidColor::Empty {
+                *color_counts.entry(layer).or_insert(0) += 1;
+            }
+        }
+        let mut colors: Vec<(&LiquidColor, usize)> = color_counts.into_iter().collect();
+        colors.sort();
+        let mut filled = vec![None; self.tubes.len()];
+        let mut states = Vec::new();
+        self.fill_tubes(&colors, &mut filled, &mut states);
+        return states;
+    }
+
+    /// Fills the unfilled tubes with the first color in `colors` in every way that fits it
+    /// exactly, and goes on to the rest of the colors for each of them
+    fn fill_tubes<'a>(
+        &self,
+        colors: &[(&'a LiquidColor, usize)],
+        filled: &mut Vec<Option<&'a LiquidColor>>,
+        states: &mut Vec<TubeState>,
+    ) {
+        let Some(((color, count), rest)) = colors.split_first() else {
+            let tubes = self
+                .tubes
+                .iter()
+                .zip(filled.iter())
+                .map(|(tube, color)| match color {
+                    Some(color) => Tube {
+                        layers: vec![(*color).clone(); tube.capacity()],
+                    },
+                    None => Tube::empty(tube.capacity()),
+                })
+                .collect();
+            states.push(TubeState { tubes });
+            return;
+        };
+        let mut capacities: Vec<usize> = (0..self.tubes.len())
+            .filter(|i| filled[*i].is_none())
+            .map(|i| self.tubes[i].capacity())
+            .collect();
+        capacities.sort();
+        capacities.dedup();
+        self.fill_color(color, *count, &capacities, rest, filled, states);
+    }
+
+    /// Fills some of the unfilled tubes of each capacity in `capacities` with `color`
+    ///
+    /// Tubes of the same capacity are interchangeable, so only the first ones of each
+    /// capacity are ever filled, which keeps the solved states distinct.
+    fn fill_color<'a>(
+        &self,
+        color: &'a LiquidColor,
+        remaining: usize,
+        capacities: &[usize],
+        rest: &[(&'a LiquidColor, usize)],
+        filled: &mut Vec<Option<&'a LiquidColor>>,
+        states: &mut Vec<TubeState>,
+    ) {
+        if remaining == 0 {
+            self.fill_tubes(rest, filled, states);
+            return;
+        }
+        let Some((capacity, larger)) = capacities.split_first() else {
+            return;
+        };
+        let unfilled: Vec<usize> = (0..self.tubes.len())
+            .filter(|i| filled[*i].is_none() && self.tubes[*i].capacity() == *capacity)
+            .collect();
+        for used in 0..=unfilled.len() {
+            if used * capacity > remaining {
+                break;
+            }
+            for i in &unfilled[..used] {
+                filled[*i] = Some(color);
+            }
+            self.fill_color(
+                color,
+                remaining - used * capacity,
+                larger,
+                rest,
+                filled,
+                states,
+            );
+            for i in &unfilled[..used] {
+                filled[*i] = None;
+            }
+        }
+    }
+
+    /// Creates the state a level file describes
+    ///
+    /// Panics if a tube holds more layers than fit in it; use `try_from_tube_array` to get
+    /// an error instead.
     pub fn from_tube_array(tube_array: TubeArray) -> TubeState {
+        match TubeState::try_from_tube_array(tube_array) {
+            Ok(state) => return state,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Creates the state a level file describes, or returns `SolveError::InvalidInput` if a
+    /// tube holds more layers than fit in it
+    ///
+    /// ```
+    /// use tubes::{SolveError, TubeArray, TubeState};
+    /// let tube_array: TubeArray =
+    ///     toml::from_str("tubes = [['red', 'red', 'red', 'red', 'red'], []]").unwrap();
+    /// assert!(matches!(
+    ///     TubeState::try_from_tube_array(tube_array),
+    ///     Err(SolveError::InvalidInput(_))
+    /// ));
+    /// ```
+    pub fn try_from_tube_array(tube_array: TubeArray) -> Result<TubeState, SolveError> {
+        let palette = tube_array.palette();
+        let capacity = tube_array.capacity.unwrap_or(DEFAULT_CAPACITY);
+        let capacities = tube_array.capacities.unwrap_or_default();
         let mut tube_vector = Vec::new();
-        for color_vec in tube_array.tubes {
-            tube_vector.push(Tube::from_vec(color_vec));
+        for (ti, names) in tube_array.tubes.iter().enumerate() {
+            let tube_capacity = capacities.get(ti).copied().unwrap_or(capacity);
+            if names.len() > tube_capacity {
+                return Err(SolveError::InvalidInput(format!(
+                    "tube {} has {} layers but only holds {}",
+                    ti,
+                    names.len(),
+                    tube_capacity
+                )));
+            }
+            // Every name is either a named color or in the palette made from these names
+            let color_vec = names.iter().map(|n| palette.color(n).unwrap()).collect();
+            tube_vector.push(Tube::from_vec_with_capacity(color_vec, tube_capacity));
         }
-        return TubeState { tubes: tube_vector };
+        return Ok(TubeState { tubes: tube_vector });
     }
 
-    pub fn to_tube_array(&self) -> TubeArray {
+    /// Returns the level file for the state, naming its colors from `palette`
+    pub fn to_tube_array(&self, palette: &Palette) -> TubeArray {
         let mut tube_vector = Vec::new();
         for tube in &self.tubes {
-            tube_vector.push(tube.to_vec());
+            tube_vector.push(tube.layers.iter().map(|l| palette.color_name(l)).collect());
         }
-        return TubeArray { tubes: tube_vector };
+        let capacities: Vec<usize> = self.tubes.iter().map(|t| t.capacity()).collect();
+        if capacities.windows(2).all(|w| w[0] == w[1]) {
+            return TubeArray {
+                capacity: capacities.first().copied(),
+                capacities: None,
+                tubes: tube_vector,
+            };
+        }
+        return TubeArray {
+            capacity: None,
+            capacities: Some(capacities),
+            tubes: tube_vector,
+        };
     }
 }
 
+/// Reads one of the level files in `levels/`, for the tests
+#[cfg(test)]
+pub(crate) fn load_level(path: &str) -> TubeState {
+    let contents = std::fs::read_to_string(path).unwrap();
+    let tube_array: TubeArray = toml::from_str(&contents).unwrap();
+    return TubeState::from_tube_array(tube_array);
+}
+
 impl fmt::Display for TubeState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}", self.display(&Palette::default()));
+    }
+}
+
+impl TubeState {
+    /// Returns something that draws the state like `Display` does, with the colors from
+    /// the level's palette
+    pub fn display<'a>(&'a self, palette: &'a Palette) -> TubeStateDisplay<'a> {
+        return TubeStateDisplay {
+            state: self,
+            palette,
+        };
+    }
+}
+
+/// Draws a `TubeState` with the colors of a `Palette`, see `TubeState::display`
+pub struct TubeStateDisplay<'a> {
+    state: &'a TubeState,
+    palette: &'a Palette,
+}
+
+impl fmt::Display for TubeStateDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let height = self
+            .state
+            .tubes
+            .iter()
+            .map(|t| t.capacity())
+            .max()
+            .unwrap_or(0);
         let mut text_lines = Vec::new();
-        for _ in 0..4 {
+        for _ in 0..height {
             text_lines.push(String::from(" "))
         }
-        let mut ti = 0;
-        for mut t in self.tubes.clone() {
-            for (i, l) in t.layers.iter_mut().enumerate() {
-                let label = if i == 3 {
+        for (ti, t) in self.state.tubes.iter().enumerate() {
+            // Shorter tubes are drawn with their bottoms lined up with the others
+            let offset = height - t.capacity();
+            for line in text_lines.iter_mut().take(offset) {
+                line.push_str("     ");
+            }
+            for (i, l) in t.layers.iter().enumerate() {
+                let label = if offset + i == height - 1 {
                     ti.to_string()
                 } else {
                     String::from(" ")
                 };
-                text_lines[i].push_str(&label);
-                text_lines[i].push_str(&"||  ".color(l.to_colored_color()).to_string());
+                text_lines[offset + i].push_str(&label);
+                let cell = if l == &LiquidColor::Unknown {
+                    "??  "
+                } else {
+                    "||  "
+                };
+                text_lines[offset + i]
+                    .push_str(&cell.color(l.to_colored_color(self.palette)).to_string());
             }
-            ti += 1;
         }
-        write!(
-            f,
-            "{}\n{}\n{}\n{}\n",
-            text_lines[0], text_lines[1], text_lines[2], text_lines[3]
-        )
+        for line in text_lines {
+            writeln!(f, "{}", line)?;
+        }
+        return Ok(());
     }
 }
 
@@ -502,7 +1060,7 @@ mod tube_state_test {
             LiquidColor::Blue,
             LiquidColor::Orange,
         );
-        let tube3 = EMPTY_TUBE;
+        let tube3 = Tube::empty(DEFAULT_CAPACITY);
         let state = TubeState {
             tubes: vec![tube1, tube2, tube3],
         };
@@ -519,6 +1077,27 @@ mod tube_state_test {
 /// by transfering the contents of one tube into another according to the rules of the
 /// transfer function.
 pub fn neighbors(state: &TubeState) -> Vec<(TransferAction, TubeState)> {
+    return neighbors_with_rules(state, Rules::WholeBlock);
+}
+
+/// Returns the neighboring states that can be reached with one transfer under the given
+/// rules
+///
+/// ```
+/// use tubes::{neighbors_with_rules, LiquidColor, Rules, Tube, TubeState};
+/// let state = TubeState {
+///     tubes: vec![
+///         Tube::from_vec(vec![LiquidColor::Blue, LiquidColor::Blue, LiquidColor::Red]),
+///         Tube::from_vec(vec![LiquidColor::Blue, LiquidColor::Blue, LiquidColor::Red]),
+///     ],
+/// };
+/// // Only one of the two blue layers fits in the other tube
+/// assert_eq!(neighbors_with_rules(&state, Rules::WholeBlock).len(), 0);
+/// let partial = neighbors_with_rules(&state, Rules::PartialPour);
+/// assert_eq!(partial.len(), 2);
+/// assert_eq!(partial[0].1.tubes[0], Tube::from_vec(vec![LiquidColor::Blue, LiquidColor::Red]));
+/// ```
+pub fn neighbors_with_rules(state: &TubeState, rules: Rules) -> Vec<(TransferAction, TubeState)> {
     let mut neighboring_states = Vec::new();
     for (si, send_tube) in state.tubes.iter().enumerate() {
         for (ri, recv_tube) in state.tubes.iter().enumerate() {
@@ -526,7 +1105,7 @@ pub fn neighbors(state: &TubeState) -> Vec<(TransferAction, TubeState)> {
             if si == ri {
                 continue;
             };
-            let transfer_result = transfer(send_tube.clone(), recv_tube.clone());
+            let transfer_result = transfer(send_tube.clone(), recv_tube.clone(), rules);
             if transfer_result.success {
                 let mut neighboring_state = state.clone();
                 neighboring_state.tubes[si] = transfer_result.send_tube;
@@ -622,65 +1201,440 @@ mod neighbors_tests {
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct TubeStateNode {
     pub actions: Vec<TransferAction>,
     pub state: TubeState,
 }
 
 /// Solves the game of tubes using bfs
-pub fn solve_bfs(initial_state: &TubeState) -> TubeStateNode {
-    let mut explored: Vec<TubeState> = Vec::new();
-    let mut q: VecDeque<TubeStateNode> = VecDeque::new();
-    explored.push(initial_state.clone());
-    q.push_back(TubeStateNode {
-        actions: vec![TransferAction {
-            send_idx: 0,
-            recv_idx: 0,
-        }],
-        state: initial_state.clone(),
-    });
-    let mut solution_state = None;
-    while !q.is_empty() {
-        let v = q.pop_front();
-        match v {
-            Some(y) => {
-                if y.state.issolved() {
-                    solution_state = Some(y);
-                    break;
-                } else {
-                    for (action, state) in neighbors(&y.state) {
-                        if !explored.contains(&&state) {
-                            explored.push(state.clone());
-                            let mut actions = y.actions.clone();
-                            actions.push(action);
-                            q.push_back(TubeStateNode {
-                                actions: actions,
-                                state: state.clone(),
-                            });
-                        }
-                    }
+///
+/// States that have already been queued are kept in a hash set so checking whether a
+/// neighboring state is new takes constant time instead of a scan over every state seen.
+/// The set holds the canonical form of each state so reorderings of the same tubes are
+/// only explored once. The search runs on `CompactState`s, so each state in the set and
+/// the queue only takes a few dozen bytes.
+///
+/// Returns `SolveError::InvalidInput` if the state fails `TubeState::validate` and
+/// `SolveError::Unsolvable` if every reachable state was explored without finding a
+/// solved one.
+pub fn solve_bfs(initial_state: &TubeState) -> Result<TubeStateNode, SolveError> {
+    return solve_bfs_with_config(initial_state, &SearchConfig::default());
+}
+
+/// Solves the game of tubes using bfs, giving up when a limit in the config is hit
+pub fn solve_bfs_with_config(
+    initial_state: &TubeState,
+    config: &SearchConfig,
+) -> Result<TubeStateNode, SolveError> {
+    return Bfs.solve(initial_state, config).result;
+}
+
+/// Breadth first search, which always finds a solution with the fewest transfers
+pub struct Bfs;
+
+impl Solver for Bfs {
+    fn name(&self) -> &str {
+        return "bfs";
+    }
+
+    fn solve(&self, initial_state: &TubeState, config: &SearchConfig) -> SearchReport {
+        let mut search = SearchContext::new(config);
+        let result = bfs(initial_state, &mut search);
+        return search.finish(result);
+    }
+}
+
+fn bfs(initial_state: &TubeState, search: &mut SearchContext) -> Result<TubeStateNode, SolveError> {
+    initial_state.validate()?;
+    let layout = CompactLayout::new(initial_state);
+    let initial = layout.encode(initial_state);
+    let mut explored: HashSet<CompactState> = HashSet::new();
+    let mut tree = MoveTree::new();
+    let mut q: VecDeque<CompactNode> = VecDeque::new();
+    if initial_state.issolved() {
+        return Ok(TubeStateNode {
+            actions: vec![],
+            state: initial_state.clone(),
+        });
+    }
+    explored.insert(layout.canonical(&initial));
+    q.push_back(CompactNode::root(initial));
+    while let Some(y) = q.pop_front() {
+        search.expand(y.depth as usize)?;
+        let previous = tree.last_move(y.node);
+        for (action, state) in layout.successors(&y.state, previous.as_ref(), search.config()) {
+            search.generated();
+            if explored.insert(layout.canonical(&state)) {
+                let node = tree.push(y.node, &action);
+                // Checking states as they are found, rather than when they come off the
+                // queue, saves expanding the rest of the layer before the solved state
+                if layout.issolved(&state) {
+                    return Ok(TubeStateNode {
+                        state: layout.decode(&state),
+                        actions: tree.actions(node),
+                    });
                 }
+                q.push_back(CompactNode {
+                    node,
+                    depth: y.depth + 1,
+                    state,
+                });
+            } else {
+                search.duplicate();
             }
-            None => break,
         }
+        search.frontier(q.len());
     }
-    match solution_state {
-        None => {
-            return TubeStateNode {
-                actions: vec![TransferAction {
-                    send_idx: 0,
-                    recv_idx: 0,
-                }],
-                state: TubeState { tubes: vec![] },
-            };
+    return Err(SolveError::Unsolvable);
+}
+
+#[cfg(test)]
+mod solve_tests {
+    use super::*;
+
+    #[test]
+    fn test_bfs_level_3() {
+        let solution = solve_bfs(&load_level("levels/3.toml")).unwrap();
+        assert!(solution.state.issolved());
+        assert_eq!(solution.actions.len(), 10);
+    }
+
+    #[test]
+    fn test_bfs_level_8() {
+        let solution = solve_bfs(&load_level("levels/8.toml")).unwrap();
+        assert!(solution.state.issolved());
+        assert_eq!(solution.actions.len(), 16);
+    }
+
+    #[test]
+    fn test_astar_level_8() {
+        let solution = solve_astar(&load_level("levels/8.toml")).unwrap();
+        assert!(solution.state.issolved());
+        assert_eq!(solution.actions.len(), 16);
+    }
+
+    #[test]
+    fn test_canonical_search_reports_real_tube_indices() {
+        let initial_state = load_level("levels/7.toml");
+        for solution in [solve_bfs(&initial_state), solve_astar(&initial_state)] {
+            let solution = solution.unwrap();
+            assert_eq!(solution.actions.len(), 15);
+            let final_state = replay(&initial_state, &solution.actions)
+                .unwrap()
+                .pop()
+                .unwrap();
+            assert_eq!(final_state, solution.state);
+            assert!(final_state.issolved());
+        }
+    }
+
+    #[test]
+    fn test_unsolvable() {
+        let state = TubeState {
+            tubes: vec![
+                Tube::from_vec(vec![
+                    LiquidColor::Orange,
+                    LiquidColor::Blue,
+                    LiquidColor::Orange,
+                    LiquidColor::Blue,
+                ]),
+                Tube::from_vec(vec![
+                    LiquidColor::Blue,
+                    LiquidColor::Orange,
+                    LiquidColor::Blue,
+                    LiquidColor::Orange,
+                ]),
+            ],
+        };
+        assert_eq!(solve_bfs(&state).err(), Some(SolveError::Unsolvable));
+        assert_eq!(solve_astar(&state).err(), Some(SolveError::Unsolvable));
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        let missing_layer = TubeState {
+            tubes: vec![
+                Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Orange]),
+                Tube::from_vec(vec![LiquidColor::Orange]),
+            ],
+        };
+        let gap = TubeState {
+            tubes: vec![Tube::from_vec(vec![
+                LiquidColor::Empty,
+                LiquidColor::Empty,
+                LiquidColor::Orange,
+                LiquidColor::Empty,
+            ])],
+        };
+        let no_tubes = TubeState { tubes: vec![] };
+        for state in [missing_layer, gap, no_tubes] {
+            assert!(matches!(
+                solve_bfs(&state),
+                Err(SolveError::InvalidInput(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_search_limits() {
+        let state = load_level("levels/8.toml");
+        let config = SearchConfig {
+            max_nodes: Some(10),
+            ..SearchConfig::default()
+        };
+        assert_eq!(
+            solve_bfs_with_config(&state, &config).err(),
+            Some(SolveError::SearchLimit(Limit::Nodes(10)))
+        );
+        assert_eq!(
+            solve_astar_with_config(&state, &config).err(),
+            Some(SolveError::SearchLimit(Limit::Nodes(10)))
+        );
+        let config = SearchConfig {
+            deadline: Some(std::time::Instant::now()),
+            ..SearchConfig::default()
+        };
+        assert_eq!(
+            solve_bfs_with_config(&state, &config).err(),
+            Some(SolveError::SearchLimit(Limit::Time))
+        );
+        let cancel = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let config = SearchConfig {
+            cancel: Some(cancel),
+            ..SearchConfig::default()
+        };
+        assert_eq!(
+            solve_astar_with_config(&state, &config).err(),
+            Some(SolveError::SearchLimit(Limit::Cancelled))
+        );
+    }
+
+    #[test]
+    fn test_breadth_first_searches_stop_at_the_same_depth() {
+        // Each one checks states for a solution as soon as they are found, so none of them
+        // expands anything as deep as the solution
+        for level in 1..=8 {
+            let initial_state = load_level(&format!("levels/{}.toml", level));
+            for algorithm in [
+                Algorithm::Bfs,
+                Algorithm::ParallelBfs,
+                Algorithm::ExternalBfs,
+            ] {
+                let report = algorithm
+                    .solver()
+                    .solve(&initial_state, &SearchConfig::default());
+                let solution = report.result.unwrap();
+                assert_eq!(
+                    report.stats.max_depth,
+                    solution.actions.len() - 1,
+                    "{:?} on level {}",
+                    algorithm,
+                    level
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_every_algorithm_solves_the_levels() {
+        let optimal_lengths = [1, 7, 10, 10, 16, 12, 15];
+        for (i, optimal_length) in optimal_lengths.iter().enumerate() {
+            let initial_state = load_level(&format!("levels/{}.toml", i + 1));
+            for algorithm in Algorithm::ALL {
+                let solver = algorithm.solver();
+                let report = solver.solve(&initial_state, &SearchConfig::default());
+                let solution = report.result.unwrap();
+                assert_eq!(
+                    replay(&initial_state, &solution.actions)
+                        .unwrap()
+                        .pop()
+                        .unwrap(),
+                    solution.state
+                );
+                assert!(solution.state.issolved());
+                if algorithm.finds_shortest() {
+                    assert_eq!(solution.actions.len(), *optimal_length, "{}", solver.name());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_every_algorithm_solves_other_capacities() {
+        for (path, capacity, optimal_length) in [
+            ("levels/capacity-3.toml", 3, 10),
+            ("levels/capacity-6.toml", 6, 13),
+        ] {
+            let initial_state = load_level(path);
+            assert!(initial_state.tubes.iter().all(|t| t.capacity() == capacity));
+            for algorithm in Algorithm::ALL {
+                let solver = algorithm.solver();
+                let report = solver.solve(&initial_state, &SearchConfig::default());
+                let solution = report.result.unwrap();
+                assert_eq!(
+                    replay(&initial_state, &solution.actions)
+                        .unwrap()
+                        .pop()
+                        .unwrap(),
+                    solution.state
+                );
+                assert!(solution.state.issolved());
+                if algorithm.finds_shortest() {
+                    assert_eq!(solution.actions.len(), optimal_length, "{}", solver.name());
+                }
+            }
+            let round_trip =
+                TubeState::from_tube_array(initial_state.to_tube_array(&Palette::default()));
+            assert_eq!(round_trip, initial_state);
+        }
+    }
+
+    #[test]
+    fn test_every_algorithm_solves_mixed_capacities() {
+        let initial_state = load_level("levels/mixed.toml");
+        let capacities: Vec<usize> = initial_state.tubes.iter().map(|t| t.capacity()).collect();
+        assert_eq!(capacities, vec![6, 3, 3, 3, 3]);
+        let unpruned = SearchConfig {
+            prune_moves: false,
+            ..SearchConfig::default()
+        };
+        let optimal_length = Bfs
+            .solve(&initial_state, &unpruned)
+            .result
+            .unwrap()
+            .actions
+            .len();
+        for algorithm in Algorithm::ALL {
+            let solver = algorithm.solver();
+            let report = solver.solve(&initial_state, &SearchConfig::default());
+            let solution = report.result.unwrap();
+            assert_eq!(
+                replay(&initial_state, &solution.actions)
+                    .unwrap()
+                    .pop()
+                    .unwrap(),
+                solution.state
+            );
+            assert!(solution.state.issolved());
+            if algorithm.finds_shortest() {
+                assert_eq!(solution.actions.len(), optimal_length, "{}", solver.name());
+            }
+        }
+        let round_trip =
+            TubeState::from_tube_array(initial_state.to_tube_array(&Palette::default()));
+        assert_eq!(round_trip, initial_state);
+    }
+
+    #[test]
+    fn test_shortest_solvers_on_mixed_capacities() {
+        let red_pair = || Tube::from_vec_with_capacity(vec![LiquidColor::Red; 2], 2);
+        let mut states = vec![
+            // Red can end up spread over three tubes of different sizes
+            TubeState {
+                tubes: vec![
+                    red_pair(),
+                    Tube::from_vec(vec![LiquidColor::Red; 4]),
+                    Tube::empty(2),
+                    Tube::from_vec_with_capacity(vec![LiquidColor::Red; 2], 3),
+                    Tube::empty(4),
+                ],
+            },
+            TubeState {
+                tubes: vec![
+                    Tube::from_vec_with_capacity(vec![LiquidColor::Red; 2], 3),
+                    Tube::from_vec(vec![LiquidColor::Red; 4]),
+                    Tube::empty(2),
+                    Tube::empty(2),
+                ],
+            },
+            load_level("levels/mixed.toml"),
+        ];
+        // Some of the numbered levels with a short and a tall tube in place of the empty
+        // ones, leaving out the ones that take idastar too long
+        for level in [2, 3, 4, 6] {
+            let mut state = load_level(&format!("levels/{}.toml", level));
+            state.tubes.retain(|t| !t.isempty());
+            state.tubes.push(Tube::empty(6));
+            state.tubes.push(Tube::empty(2));
+            states.push(state);
         }
-        Some(solution) => {
-            let mut solution_actions = solution.actions.clone();
-            solution_actions.remove(0);
-            return TubeStateNode {
-                actions: solution_actions,
-                state: solution.state,
+        let unpruned = SearchConfig {
+            prune_moves: false,
+            ..SearchConfig::default()
+        };
+        let config = SearchConfig::default();
+        for state in states {
+            let optimal_length = match Bfs.solve(&state, &unpruned).result {
+                Ok(solution) => solution.actions.len(),
+                Err(e) => {
+                    assert_eq!(e, SolveError::Unsolvable);
+                    continue;
+                }
             };
+            assert!(lower_bound(&state) as usize <= optimal_length);
+            for algorithm in [Algorithm::AStar, Algorithm::IdaStar, Algorithm::Anytime] {
+                let report = algorithm.solver().solve(&state, &config);
+                let solution = report.result.unwrap();
+                assert!(solution.state.issolved());
+                assert_eq!(lower_bound(&solution.state), 0);
+                assert_eq!(solution.actions.len(), optimal_length, "{:?}", algorithm);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mixed_capacities_have_to_fill_exactly() {
+        let fits = TubeState {
+            tubes: vec![
+                Tube::from_vec_with_capacity(vec![LiquidColor::Blue; 3], 3),
+                Tube::from_vec(vec![]),
+            ],
+        };
+        assert!(fits.validate().is_ok());
+        let too_little = TubeState {
+            tubes: vec![
+                Tube::from_vec_with_capacity(vec![LiquidColor::Blue; 2], 3),
+                Tube::from_vec(vec![]),
+            ],
+        };
+        assert!(matches!(
+            too_little.validate(),
+            Err(SolveError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_algorithm_names() {
+        for algorithm in Algorithm::ALL {
+            assert_eq!(algorithm.solver().name().parse(), Ok(algorithm));
         }
+        assert!("nope".parse::<Algorithm>().is_err());
+    }
+
+    #[test]
+    fn test_search_stats_and_progress() {
+        let calls = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let progress_calls = calls.clone();
+        let config = SearchConfig {
+            progress: Some(std::sync::Arc::new(move |stats: &SearchStats| {
+                progress_calls.lock().unwrap().push(stats.clone());
+            })),
+            ..SearchConfig::default()
+        };
+        let report = Bfs.solve(&load_level("levels/8.toml"), &config);
+        let solution = report.result.unwrap();
+        let stats = report.stats;
+        assert!(stats.nodes_expanded > 0);
+        assert!(stats.nodes_generated >= stats.nodes_expanded);
+        assert!(stats.duplicates_pruned > 0);
+        assert!(stats.duplicates_pruned < stats.nodes_generated);
+        assert!(stats.peak_frontier > 0);
+        assert!(stats.max_depth <= solution.actions.len());
+        let calls = calls.lock().unwrap();
+        let last_call = calls.last().unwrap();
+        assert_eq!(calls.len(), 1 + stats.nodes_expanded / PROGRESS_INTERVAL);
+        assert_eq!(last_call.nodes_expanded, stats.nodes_expanded);
+        assert_eq!(last_call.nodes_generated, stats.nodes_generated);
     }
 }
@@ -1,7 +1,20 @@
+#![allow(clippy::needless_return)]
+// Older code that these lints would flag is left as it was written
+#![allow(
+    clippy::single_component_path_imports,
+    clippy::collapsible_if,
+    clippy::needless_borrows_for_generic_args,
+    clippy::redundant_closure,
+    clippy::unnecessary_cast
+)]
+
 use clap::{AppSettings, Parser, Subcommand};
 use std::fs;
-use std::io::Write;
+use std::io::{self, IsTerminal, Write};
 use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tubes::*;
 
 mod finder;
@@ -20,6 +33,103 @@ enum Commands {
     Solve {
         #[clap(parse(from_os_str))]
         tubes_file_path: std::path::PathBuf,
+        /// Give up after expanding this many states
+        #[clap(long)]
+        max_nodes: Option<usize>,
+        /// Give up after this many seconds
+        #[clap(long)]
+        timeout: Option<f64>,
+        /// The search strategy: bfs, dfs, astar, idastar, parallel-bfs, bidirectional,
+        /// external-bfs, beam, greedy, anytime or uniform-cost, astar by default
+        #[clap(long)]
+        algorithm: Option<Algorithm>,
+        /// How many states the beam algorithm keeps at each depth
+        #[clap(long, default_value_t = DEFAULT_BEAM_WIDTH)]
+        beam_width: usize,
+        /// Where external-bfs keeps its files, the system temporary directory by default
+        #[clap(long, parse(from_os_str))]
+        temp_dir: Option<std::path::PathBuf>,
+        /// What the solution should have the least of: pours, units or empty-tubes
+        #[clap(long, default_value = "pours")]
+        minimize: CostModel,
+        /// Try every legal move, even ones that can never be part of a shortest solution
+        #[clap(long)]
+        no_prune: bool,
+        /// Which rules pours follow: whole-block or partial-pour
+        #[clap(long, default_value = "whole-block")]
+        rules: Rules,
+    },
+    /// List every solution with the fewest moves, or the k shortest solutions
+    #[clap(setting(AppSettings::ArgRequiredElseHelp))]
+    Solutions {
+        #[clap(parse(from_os_str))]
+        tubes_file_path: std::path::PathBuf,
+        /// List the k shortest solutions, including longer ones, instead of only the shortest
+        #[clap(long)]
+        k: Option<usize>,
+        /// List at most this many of the shortest solutions
+        #[clap(long, default_value = "100")]
+        max_solutions: usize,
+        /// Group solutions that only differ by the order of moves on different tubes
+        #[clap(long)]
+        group: bool,
+        /// Give up after expanding this many states
+        #[clap(long)]
+        max_nodes: Option<usize>,
+        /// Give up after this many seconds
+        #[clap(long)]
+        timeout: Option<f64>,
+        /// Which rules pours follow: whole-block or partial-pour
+        #[clap(long, default_value = "whole-block")]
+        rules: Rules,
+    },
+    /// Check that a list of moves is legal and solves a level
+    #[clap(setting(AppSettings::ArgRequiredElseHelp))]
+    Verify {
+        #[clap(parse(from_os_str))]
+        tubes_file_path: std::path::PathBuf,
+        /// Moves like 0->3 separated by commas, spaces or new lines
+        #[clap(parse(from_os_str))]
+        moves_file_path: std::path::PathBuf,
+        /// Which rules pours follow: whole-block or partial-pour
+        #[clap(long, default_value = "whole-block")]
+        rules: Rules,
+    },
+    /// Show the next move of a shortest solution from a game in progress
+    #[clap(setting(AppSettings::ArgRequiredElseHelp))]
+    Hint {
+        /// A tubes file, or a screenshot of the game
+        #[clap(parse(from_os_str))]
+        state_file_path: std::path::PathBuf,
+        /// Give up after expanding this many states
+        #[clap(long)]
+        max_nodes: Option<usize>,
+        /// Give up after this many seconds
+        #[clap(long)]
+        timeout: Option<f64>,
+        /// Which rules pours follow: whole-block or partial-pour
+        #[clap(long, default_value = "whole-block")]
+        rules: Rules,
+        /// How to pick a move when some layers are hidden: robust or informative, which look
+        /// one move ahead, or safe, which plans for every way the hidden layers could be
+        /// filled in
+        #[clap(long, default_value = "robust")]
+        strategy: HiddenStrategy,
+    },
+    /// Find the move in a played game that made the level impossible to solve
+    #[clap(setting(AppSettings::ArgRequiredElseHelp))]
+    Review {
+        #[clap(parse(from_os_str))]
+        tubes_file_path: std::path::PathBuf,
+        /// Moves like 0->3 separated by commas, spaces or new lines
+        #[clap(parse(from_os_str))]
+        moves_file_path: std::path::PathBuf,
+        /// Give up on each search after expanding this many states
+        #[clap(long)]
+        max_nodes: Option<usize>,
+        /// Which rules pours follow: whole-block or partial-pour
+        #[clap(long, default_value = "whole-block")]
+        rules: Rules,
     },
     #[clap(setting(AppSettings::ArgRequiredElseHelp))]
     Scan {
@@ -27,40 +137,424 @@ enum Commands {
         tubes_image_path: std::path::PathBuf,
         #[clap(parse(from_os_str))]
         tubes_output_file_path: std::path::PathBuf,
+        /// Save a copy of the screenshot with every pixel that was read marked in red
+        #[clap(long, parse(from_os_str))]
+        debug_image: Option<std::path::PathBuf>,
     },
 }
 
+/// Reads a level file and the palette of its colors, exiting with an error message if it
+/// isn't valid
+fn read_tubes(tubes_file_path: &Path) -> (TubeState, Palette) {
+    let filepath = tubes_file_path.as_os_str().to_str().unwrap();
+    let contents =
+        fs::read_to_string(tubes_file_path).expect("Something went wrong while reading the file");
+    let tube_array: TubeArray = match toml::from_str(&contents) {
+        Ok(tube_array) => tube_array,
+        Err(e) => {
+            eprintln!("Could not read tubes from {}: {}", filepath, e);
+            process::exit(1);
+        }
+    };
+    let palette = tube_array.palette();
+    match TubeState::try_from_tube_array(tube_array) {
+        Ok(state) => return (state, palette),
+        Err(e) => {
+            eprintln!("Could not read tubes from {}: {}", filepath, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Reads a file of moves, exiting with an error message if it isn't valid
+fn read_moves(moves_file_path: &Path) -> Vec<TransferAction> {
+    let movespath = moves_file_path.as_os_str().to_str().unwrap();
+    let contents =
+        fs::read_to_string(moves_file_path).expect("Something went wrong while reading the file");
+    match parse_moves(&contents) {
+        Ok(actions) => return actions,
+        Err(e) => {
+            eprintln!("Could not read moves from {}: {}", movespath, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Reads a tubes file, or scans a screenshot of the game if the file isn't a tubes file
+fn read_state(state_file_path: &Path) -> (TubeState, Palette) {
+    if state_file_path.extension().is_some_and(|e| e == "toml") {
+        return read_tubes(state_file_path);
+    }
+    let level_img = image::open(state_file_path).unwrap();
+    let level_img = finder::crop_level(&level_img);
+    let tube_centers = finder::find_tubes(&level_img);
+    return finder::extract_tube_colors(&level_img, tube_centers, None);
+}
+
+/// Writes moves the way a moves file has them, separated by commas
+fn format_moves(actions: &[TransferAction]) -> String {
+    let moves: Vec<String> = actions.iter().map(|a| format!("{:#}", a)).collect();
+    return moves.join(", ");
+}
+
 fn main() {
     let args = Cli::parse();
     match &args.command {
-        Commands::Solve { tubes_file_path } => {
+        Commands::Solve {
+            tubes_file_path,
+            max_nodes,
+            timeout,
+            algorithm,
+            beam_width,
+            temp_dir,
+            minimize,
+            no_prune,
+            rules,
+        } => {
             let filepath = tubes_file_path.as_os_str().to_str().unwrap();
-            let contents = fs::read_to_string(tubes_file_path.clone())
-                .expect("Something went wrong while reading the file");
-            let tube_array: TubeArray = toml::from_str(&contents).unwrap();
-            let initial_state = TubeState::from_tube_array(tube_array);
+            let (initial_state, palette) = read_tubes(tubes_file_path);
             println!("Reading tubes from {}.", filepath);
             println!("\n-- Initial State ------------\n");
-            println!("{}", initial_state);
-            let solved_state = solve_bfs(&initial_state);
+            println!("{}", initial_state.display(&palette));
+            let show_progress = io::stderr().is_terminal();
+            let config = SearchConfig {
+                progress: if show_progress {
+                    Some(Arc::new(|stats: &SearchStats| {
+                        eprint!("\r{}", stats);
+                    }))
+                } else {
+                    None
+                },
+                max_nodes: *max_nodes,
+                prune_moves: !no_prune,
+                rules: *rules,
+                deadline: timeout
+                    .map(|secs| Instant::now() + Duration::from_secs_f64(secs.max(0.0))),
+                ..SearchConfig::default()
+            };
+            let solver: Box<dyn Solver> = match (minimize, algorithm) {
+                (CostModel::Pours, Some(Algorithm::ExternalBfs)) => Box::new(ExternalBfs {
+                    temp_dir: temp_dir.clone(),
+                    ..ExternalBfs::default()
+                }),
+                (CostModel::Pours, Some(Algorithm::Beam)) => Box::new(BeamSearch {
+                    width: *beam_width,
+                    ..BeamSearch::default()
+                }),
+                (CostModel::Pours, algorithm) => algorithm.unwrap_or(Algorithm::AStar).solver(),
+                (cost_model, None | Some(Algorithm::AStar)) => Box::new(WeightedAStar {
+                    cost_model: *cost_model,
+                }),
+                (cost_model, Some(Algorithm::UniformCost)) => Box::new(UniformCost {
+                    cost_model: *cost_model,
+                }),
+                (cost_model, Some(algorithm)) => {
+                    eprintln!(
+                        "The {} algorithm can only minimize pours, use astar or uniform-cost to minimize {}.",
+                        algorithm.solver().name(),
+                        cost_model
+                    );
+                    process::exit(1);
+                }
+            };
+            let report = match algorithm {
+                // Show each shorter solution as soon as it is found, so stopping early
+                // still leaves the best one so far on screen
+                Some(Algorithm::Anytime) => Anytime::default().solve_with_callback(
+                    &initial_state,
+                    &config,
+                    &mut |solution, stats| {
+                        if show_progress {
+                            eprintln!();
+                        }
+                        println!(
+                            "Found a solution with {} moves after {:.3}s: {}",
+                            solution.actions.len(),
+                            stats.elapsed.as_secs_f64(),
+                            format_moves(&solution.actions)
+                        );
+                    },
+                ),
+                _ => solver.solve(&initial_state, &config),
+            };
+            if show_progress {
+                eprintln!();
+            }
+            let solved_state = match report.result {
+                Ok(solved_state) => solved_state,
+                Err(e) => {
+                    eprintln!("Could not solve {}: {}.", filepath, e);
+                    eprintln!("{}", report.stats);
+                    process::exit(1);
+                }
+            };
             println!("-- Moves --------------------\n");
             for (idx, action) in solved_state.actions.iter().enumerate() {
                 println!("step {}: {}", idx + 1, action);
             }
             println!();
             println!("-- Solved State -------------\n");
-            println!("{}", solved_state.state);
+            println!("{}", solved_state.state.display(&palette));
+            if *minimize != CostModel::Pours {
+                let cost = minimize.solution_cost(&initial_state, &solved_state.actions, *rules);
+                println!(
+                    "Total cost: {}",
+                    minimize.describe(cost.unwrap_or_default())
+                );
+            }
+            if let Some(bound) = report.lower_bound {
+                if bound == solved_state.actions.len() {
+                    println!("No solution has fewer moves.");
+                } else {
+                    println!(
+                    "At most {} moves longer than the shortest solution, which takes at least {}.",
+                    solved_state.actions.len().saturating_sub(bound),
+                    bound
+                );
+                }
+            }
+            println!("Solved with {}: {}", solver.name(), report.stats);
+        }
+        Commands::Solutions {
+            tubes_file_path,
+            k,
+            max_solutions,
+            group,
+            max_nodes,
+            timeout,
+            rules,
+        } => {
+            let filepath = tubes_file_path.as_os_str().to_str().unwrap();
+            let (initial_state, _) = read_tubes(tubes_file_path);
+            let config = SearchConfig {
+                max_nodes: *max_nodes,
+                rules: *rules,
+                deadline: timeout
+                    .map(|secs| Instant::now() + Duration::from_secs_f64(secs.max(0.0))),
+                ..SearchConfig::default()
+            };
+            let listed = match k {
+                Some(k) => k_shortest_solutions(&initial_state, *k, &config)
+                    .map(|solutions| (solutions, None)),
+                None => shortest_solutions_with_count(&initial_state, *max_solutions, &config)
+                    .map(|(solutions, count)| (solutions, Some(count))),
+            };
+            let (solutions, count) = match listed {
+                Ok(listed) => listed,
+                Err(e) => {
+                    eprintln!("Could not solve {}: {}.", filepath, e);
+                    process::exit(1);
+                }
+            };
+            if let Some(count) = count {
+                let length = solutions.first().map_or(0, |s| s.len());
+                println!(
+                    "{} has {} solutions with {} moves.",
+                    filepath, count, length
+                );
+                if count > solutions.len() as u128 {
+                    println!("Showing the first {}.", solutions.len());
+                }
+            }
+            let groups = if *group {
+                group_commuting_solutions(&solutions)
+            } else {
+                solutions.into_iter().map(|s| vec![s]).collect()
+            };
+            for (idx, solutions) in groups.iter().enumerate() {
+                let lines: Vec<String> = solutions
+                    .iter()
+                    .map(|solution| format_moves(solution))
+                    .collect();
+                if *group {
+                    let plural = if lines.len() == 1 { "" } else { "s" };
+                    println!("\ngroup {} ({} ordering{}):", idx + 1, lines.len(), plural);
+                    for line in lines {
+                        println!("  {}", line);
+                    }
+                } else {
+                    println!("{}: {}", idx + 1, lines[0]);
+                }
+            }
+        }
+        Commands::Verify {
+            tubes_file_path,
+            moves_file_path,
+            rules,
+        } => {
+            let (initial_state, palette) = read_tubes(tubes_file_path);
+            let movespath = moves_file_path.as_os_str().to_str().unwrap();
+            let actions = read_moves(moves_file_path);
+            let states = match replay_with_rules(&initial_state, &actions, *rules) {
+                Ok(states) => states,
+                Err(e) => {
+                    eprintln!("{}: {}.", movespath, e);
+                    process::exit(1);
+                }
+            };
+            let final_state = states.last().unwrap();
+            println!("{}", final_state.display(&palette));
+            if !final_state.issolved() {
+                eprintln!(
+                    "All {} moves are legal but the level is not solved.",
+                    actions.len()
+                );
+                process::exit(1);
+            }
+            println!("All {} moves are legal and solve the level.", actions.len());
+        }
+        Commands::Hint {
+            state_file_path,
+            max_nodes,
+            timeout,
+            rules,
+            strategy,
+        } => {
+            let filepath = state_file_path.as_os_str().to_str().unwrap();
+            let (state, palette) = read_state(state_file_path);
+            println!("{}", state.display(&palette));
+            let config = SearchConfig {
+                max_nodes: *max_nodes,
+                rules: *rules,
+                deadline: timeout
+                    .map(|secs| Instant::now() + Duration::from_secs_f64(secs.max(0.0))),
+                ..SearchConfig::default()
+            };
+            if state.hidden_count() > 0 {
+                match one_move_hidden_hint(&state, *strategy, &config) {
+                    Ok(HiddenHint {
+                        action: Some(action),
+                        possibilities,
+                        worst_moves_left,
+                        outcomes,
+                        ..
+                    }) if *strategy == HiddenStrategy::Safe => {
+                        println!("Next move: {}.", action);
+                        println!(
+                            "Solves the level in at most {} moves whichever of the {} ways the hidden layers are filled in, if each later move follows what the earlier ones revealed.",
+                            worst_moves_left, possibilities
+                        );
+                        println!("Could reveal {} different states.", outcomes);
+                    }
+                    Ok(HiddenHint {
+                        action: Some(action),
+                        possibilities,
+                        winnable,
+                        worst_moves_left,
+                        outcomes,
+                    }) => {
+                        println!("Next move: {}.", action);
+                        println!(
+                            "Solvable afterwards for {} of the {} ways the hidden layers could be filled in, if every hidden color were then known.",
+                            winnable, possibilities
+                        );
+                        println!("At most {} moves left if so.", worst_moves_left);
+                        println!("Could reveal {} different states.", outcomes);
+                        println!(
+                            "This only looks one move ahead, later moves may still need a guess."
+                        );
+                    }
+                    Ok(HiddenHint { action: None, .. }) => {
+                        println!("{} is already solved.", filepath)
+                    }
+                    Err(SolveError::Unsolvable) if *strategy == HiddenStrategy::Safe => {
+                        println!(
+                            "No move from {} is sure to solve it, some ways the hidden layers could be filled in need a guess.",
+                            filepath
+                        )
+                    }
+                    Err(SolveError::Unsolvable) => {
+                        println!("{} is a dead end whatever the hidden layers are.", filepath)
+                    }
+                    Err(e @ SolveError::SearchLimit(Limit::Possibilities(_))) => {
+                        eprintln!(
+                            "Could not find a hint for {}: {}, try --strategy safe.",
+                            filepath, e
+                        );
+                        process::exit(1);
+                    }
+                    Err(e) => {
+                        eprintln!("Could not find a hint for {}: {}.", filepath, e);
+                        process::exit(1);
+                    }
+                }
+                return;
+            }
+            match hint(&state, &config) {
+                Ok(Hint {
+                    action: Some(action),
+                    moves_left,
+                }) => println!("Next move: {} ({} moves left).", action, moves_left),
+                Ok(Hint { action: None, .. }) => println!("{} is already solved.", filepath),
+                Err(SolveError::Unsolvable) => {
+                    println!("{} is a dead end, no moves lead to a solution.", filepath)
+                }
+                Err(e) => {
+                    eprintln!("Could not find a hint for {}: {}.", filepath, e);
+                    process::exit(1);
+                }
+            }
+        }
+        Commands::Review {
+            tubes_file_path,
+            moves_file_path,
+            max_nodes,
+            rules,
+        } => {
+            let (initial_state, _) = read_tubes(tubes_file_path);
+            let actions = read_moves(moves_file_path);
+            let config = SearchConfig {
+                max_nodes: *max_nodes,
+                rules: *rules,
+                ..SearchConfig::default()
+            };
+            let review = match review_moves(&initial_state, &actions, &config) {
+                Ok(review) => review,
+                Err(e) => {
+                    eprintln!("Could not review the moves: {}.", e);
+                    process::exit(1);
+                }
+            };
+            for (idx, action) in actions.iter().enumerate() {
+                let label = if review.winnable[idx + 1] {
+                    "winnable"
+                } else {
+                    "lost"
+                };
+                println!("step {}: {} ({})", idx + 1, action, label);
+            }
+            println!();
+            if !review.winnable[0] {
+                println!("The level could not be solved from the start.");
+            } else if let Some(dead_end) = review.dead_end() {
+                println!(
+                    "Step {} ({}) made the level impossible to solve.",
+                    dead_end + 1,
+                    actions[dead_end]
+                );
+                println!(
+                    "Moves since the level could last be won: {}",
+                    review.moves_since_winnable()
+                );
+            } else {
+                println!("The level can still be solved after every move.");
+            }
         }
         Commands::Scan {
             tubes_image_path,
             tubes_output_file_path,
+            debug_image,
         } => {
             let level_img = image::open(&Path::new(tubes_image_path)).unwrap();
             let level_img = finder::crop_level(&level_img);
             let tube_centers = finder::find_tubes(&level_img);
-            let tubes = finder::extract_tube_colors(&level_img, tube_centers.clone());
+            let (tubes, palette) = finder::extract_tube_colors(
+                &level_img,
+                tube_centers.clone(),
+                debug_image.as_deref(),
+            );
             let mut output = fs::File::create(tubes_output_file_path).unwrap();
-            let serialized_tubes = toml::to_string(&tubes.to_tube_array()).unwrap();
+            let serialized_tubes = toml::to_string(&tubes.to_tube_array(&palette)).unwrap();
             let _ = write!(output, "{}", serialized_tubes);
         }
     }
@@ -0,0 +1,149 @@
+use crate::LiquidColor;
+use std::fmt;
+
+/// A color that isn't one of the named ones, stored as its place in the level's `Palette`
+///
+/// Comparing, hashing and copying a color only ever touch this number. Palettes keep
+/// their names sorted, so ids order the same way the names do and `canonical` states
+/// don't depend on the order colors appear in the level.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct ColorId(pub u32);
+
+impl fmt::Display for ColorId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "color {}", self.0)
+    }
+}
+
+/// The names of the colors in one level that aren't one of the named ones
+///
+/// Level files and the scanner name these colors with hex codes like `81d385`, or any
+/// other string. Each name is stored once, and the tubes of the level refer to it by its
+/// `ColorId`.
+///
+/// ```
+/// use tubes::{ColorId, LiquidColor, Palette};
+/// let palette = Palette::from_names(["db9051", "orange", "81d385", "db9051"]);
+/// assert_eq!(palette.len(), 2);
+/// let green = palette.color("81d385").unwrap();
+/// assert_eq!(green, LiquidColor::Other(ColorId(0)));
+/// assert!(palette.color("db9051").unwrap() > green);
+/// assert_eq!(palette.color("orange"), Some(LiquidColor::Orange));
+/// assert_eq!(palette.color("5c6bc0"), None);
+/// assert_eq!(palette.color_name(&green), "81d385");
+/// assert_eq!(palette.rgb(ColorId(0)), Some([0x81, 0xd3, 0x85]));
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Palette {
+    names: Vec<String>,
+}
+
+impl Palette {
+    /// Creates the palette for the colors with the given names, skipping the named colors
+    pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Palette {
+        let mut names: Vec<String> = names
+            .into_iter()
+            .filter(|name| LiquidColor::from_name(name).is_none())
+            .map(String::from)
+            .collect();
+        names.sort();
+        names.dedup();
+        return Palette { names };
+    }
+
+    /// Returns the number of colors in the palette
+    pub fn len(&self) -> usize {
+        return self.names.len();
+    }
+
+    /// Returns true if the level only uses named colors
+    pub fn is_empty(&self) -> bool {
+        return self.names.is_empty();
+    }
+
+    /// Returns the color with the given name, or `None` if it isn't a named color and
+    /// isn't in the palette
+    pub fn color(&self, name: &str) -> Option<LiquidColor> {
+        if let Some(color) = LiquidColor::from_name(name) {
+            return Some(color);
+        }
+        return self
+            .names
+            .binary_search_by(|n| n.as_str().cmp(name))
+            .ok()
+            .map(|i| LiquidColor::Other(ColorId(i as u32)));
+    }
+
+    /// Returns the name a level file uses for the color
+    ///
+    /// Colors that aren't in the palette are named the way they are displayed.
+    pub fn color_name(&self, color: &LiquidColor) -> String {
+        if let LiquidColor::Other(id) = color {
+            if let Some(name) = self.names.get(id.0 as usize) {
+                return name.clone();
+            }
+        }
+        return color.to_string();
+    }
+
+    /// Returns the color as red, green and blue if its name is a hex code
+    pub fn rgb(&self, id: ColorId) -> Option<[u8; 3]> {
+        let name = self.names.get(id.0 as usize)?;
+        let mut rgb = [0u8; 3];
+        return hex::decode_to_slice(name, &mut rgb).ok().map(|_| rgb);
+    }
+}
+
+#[cfg(test)]
+mod palette_tests {
+    use super::*;
+    use crate::{Tube, TubeArray, TubeState};
+
+    #[test]
+    fn test_ids_sort_by_name() {
+        let palette = Palette::from_names(["palette-order-b", "palette-order-a"]);
+        let earlier = palette.color("palette-order-a").unwrap();
+        let later = palette.color("palette-order-b").unwrap();
+        assert!(earlier < later);
+        let state = |first: &LiquidColor, second: &LiquidColor| {
+            return TubeState {
+                tubes: vec![
+                    Tube::from_vec_with_capacity(vec![second.clone(), second.clone()], 2),
+                    Tube::from_vec_with_capacity(vec![first.clone(), first.clone()], 2),
+                    Tube::empty(2),
+                ],
+            };
+        };
+        let canonical = state(&earlier, &later).canonical();
+        assert_eq!(canonical, state(&later, &earlier).canonical());
+        assert_eq!(canonical.tubes[1].layers[0], earlier);
+    }
+
+    #[test]
+    fn test_level_files_keep_their_color_names() {
+        let contents = "tubes = [['81d385', 'orange'], ['orange', '81d385'], []]";
+        let tube_array: TubeArray = toml::from_str(contents).unwrap();
+        let palette = tube_array.palette();
+        let state = TubeState::from_tube_array(tube_array);
+        assert_eq!(state.tubes[0].layers[2], palette.color("81d385").unwrap());
+        assert_eq!(state.tubes[0].layers[3], LiquidColor::Orange);
+        let written = toml::to_string(&state.to_tube_array(&palette)).unwrap();
+        let tube_array: TubeArray = toml::from_str(&written).unwrap();
+        assert_eq!(tube_array.palette(), palette);
+        assert_eq!(TubeState::from_tube_array(tube_array), state);
+        assert!(written.contains("\"81d385\""));
+        assert!(written.contains("\"orange\""));
+    }
+
+    #[test]
+    fn test_levels_get_their_own_palettes() {
+        let first: TubeArray = toml::from_str("tubes = [['bbbbbb'], ['aaaaaa']]").unwrap();
+        let second: TubeArray = toml::from_str("tubes = [['aaaaaa'], ['cccccc']]").unwrap();
+        assert_eq!(
+            first.palette().color("aaaaaa"),
+            second.palette().color("aaaaaa")
+        );
+        assert_eq!(first.palette().color("cccccc"), None);
+        assert_eq!(second.palette().len(), 2);
+    }
+}
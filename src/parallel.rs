@@ -0,0 +1,202 @@
+use crate::search::SearchContext;
+use crate::{
+    CompactLayout, CompactNode, CompactState, MoveTree, SearchConfig, SearchReport, SolveError,
+    Solver, TransferAction, TubeState, TubeStateNode,
+};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Breadth first search that expands each layer of states across several threads
+///
+/// Every layer of states the same number of transfers from the start is split into one
+/// chunk per thread. The threads share a visited set that is split into shards behind
+/// their own locks, so they rarely wait on each other. A layer is only started once the
+/// previous one is done, so the solution has the fewest transfers, just like `Bfs`, and
+/// like `Bfs` it stores its states as `CompactState`s.
+pub struct ParallelBfs {
+    pub threads: usize,
+}
+
+impl Default for ParallelBfs {
+    fn default() -> Self {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        return ParallelBfs { threads };
+    }
+}
+
+impl Solver for ParallelBfs {
+    fn name(&self) -> &str {
+        return "parallel-bfs";
+    }
+
+    fn solve(&self, initial_state: &TubeState, config: &SearchConfig) -> SearchReport {
+        let mut search = SearchContext::new(config);
+        let result = parallel_bfs(initial_state, self.threads.max(1), &mut search);
+        return search.finish(result);
+    }
+}
+
+/// A set of states split into shards that can be inserted into from several threads
+struct ShardedSet {
+    shards: Vec<Mutex<HashSet<CompactState>>>,
+}
+
+impl ShardedSet {
+    fn new(shard_count: usize) -> ShardedSet {
+        let shards = (0..shard_count)
+            .map(|_| Mutex::new(HashSet::new()))
+            .collect();
+        return ShardedSet { shards };
+    }
+
+    /// Adds the state to the set, returning false if it was already there
+    fn insert(&self, state: CompactState) -> bool {
+        let mut hasher = DefaultHasher::new();
+        state.hash(&mut hasher);
+        let shard = hasher.finish() as usize % self.shards.len();
+        return self.shards[shard].lock().unwrap().insert(state);
+    }
+}
+
+/// The work one thread did while expanding its chunk of a layer
+#[derive(Default)]
+struct ChunkResult {
+    /// The new states and the node and move each was reached from
+    next_layer: Vec<(u32, TransferAction, CompactState)>,
+    expanded: usize,
+    generated: usize,
+    duplicates: usize,
+}
+
+fn parallel_bfs(
+    initial_state: &TubeState,
+    threads: usize,
+    search: &mut SearchContext,
+) -> Result<TubeStateNode, SolveError> {
+    initial_state.validate()?;
+    if initial_state.issolved() {
+        return Ok(TubeStateNode {
+            actions: vec![],
+            state: initial_state.clone(),
+        });
+    }
+    let layout = CompactLayout::new(initial_state);
+    let initial = layout.encode(initial_state);
+    let explored = ShardedSet::new(threads * 16);
+    explored.insert(layout.canonical(&initial));
+    let mut tree = MoveTree::new();
+    let mut layer = vec![CompactNode::root(initial)];
+    let mut depth = 0;
+    while !layer.is_empty() {
+        let expanded_before = search.stats.nodes_expanded;
+        let expanded_count = AtomicUsize::new(expanded_before);
+        let config = search.config();
+        let chunk_size = layer.len().div_ceil(threads);
+        let results: Vec<Result<ChunkResult, SolveError>> = thread::scope(|scope| {
+            let handles: Vec<_> = layer
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let explored = &explored;
+                    let expanded_count = &expanded_count;
+                    let layout = &layout;
+                    let tree = &tree;
+                    scope.spawn(move || {
+                        let mut result = ChunkResult::default();
+                        for node in chunk {
+                            config.check(expanded_count.fetch_add(1, Ordering::Relaxed))?;
+                            result.expanded += 1;
+                            let previous = tree.last_move(node.node);
+                            for (action, state) in
+                                layout.successors(&node.state, previous.as_ref(), config)
+                            {
+                                result.generated += 1;
+                                if explored.insert(layout.canonical(&state)) {
+                                    result.next_layer.push((node.node, action, state));
+                                } else {
+                                    result.duplicates += 1;
+                                }
+                            }
+                        }
+                        return Ok(result);
+                    })
+                })
+                .collect();
+            return handles.into_iter().map(|h| h.join().unwrap()).collect();
+        });
+        let mut next_layer = Vec::new();
+        let mut first_error = None;
+        for result in results {
+            match result {
+                Ok(result) => {
+                    search.record(result.expanded, result.generated, result.duplicates);
+                    for (parent, action, state) in result.next_layer {
+                        next_layer.push(CompactNode {
+                            node: tree.push(parent, &action),
+                            depth: depth as u32 + 1,
+                            state,
+                        });
+                    }
+                }
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        search.expanded_to_depth(depth);
+        if let Some(e) = first_error {
+            return Err(e);
+        }
+        if let Some(solved) = next_layer
+            .iter()
+            .position(|node| layout.issolved(&node.state))
+        {
+            let node = next_layer.swap_remove(solved);
+            return Ok(TubeStateNode {
+                actions: tree.actions(node.node),
+                state: layout.decode(&node.state),
+            });
+        }
+        search.frontier(next_layer.len());
+        layer = next_layer;
+        depth += 1;
+    }
+    return Err(SolveError::Unsolvable);
+}
+
+#[cfg(test)]
+mod parallel_tests {
+    use super::*;
+    use crate::{load_level, Bfs};
+
+    #[test]
+    fn test_parallel_matches_bfs_on_level_8() {
+        let state = load_level("levels/8.toml");
+        let config = SearchConfig::default();
+        let expected = Bfs.solve(&state, &config).result.unwrap();
+        for threads in [1, 2, 4] {
+            let report = ParallelBfs { threads }.solve(&state, &config);
+            let solution = report.result.unwrap();
+            assert!(solution.state.issolved());
+            assert_eq!(solution.actions.len(), expected.actions.len());
+            assert!(report.stats.nodes_expanded > 0);
+        }
+    }
+
+    #[test]
+    fn test_parallel_node_limit() {
+        let state = load_level("levels/8.toml");
+        let config = SearchConfig {
+            max_nodes: Some(50),
+            ..SearchConfig::default()
+        };
+        let report = ParallelBfs { threads: 4 }.solve(&state, &config);
+        assert_eq!(
+            report.result.err(),
+            Some(SolveError::SearchLimit(crate::Limit::Nodes(50)))
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{neighbors_with_rules, Rules, TransferAction, TubeState};
+
+/// Returns the neighboring states that can be on a shortest solution
+///
+/// This is `neighbors` with the moves removed that can never be part of a solution with
+/// the fewest transfers:
+///
+/// - Pouring a tube that only holds one color into an empty tube of the same size just
+///   swaps the two tubes, so the state is the same puzzle as before.
+/// - Pouring into an empty tube when an earlier tube of the same size is also empty leads
+///   to the same puzzle as pouring into the earlier one, so only the first empty tube of
+///   each size is kept.
+/// - Pouring tube B into tube A right after pouring A into B either undoes the first
+///   pour, or ends up where pouring B into A straight away would have, in one move fewer.
+///   This holds for partial pours too.
+///
+/// `previous` is the move that led to `state`, if there was one.
+///
+/// ```
+/// use tubes::LiquidColor;
+/// use tubes::Tube;
+/// use tubes::TubeState;
+/// use tubes::{neighbors, pruned_neighbors, Rules};
+/// let state = TubeState {
+///     tubes: vec![
+///         Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Orange]),
+///         Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Blue]),
+///         Tube::from_vec(vec![]),
+///         Tube::from_vec(vec![]),
+///     ],
+/// };
+/// assert_eq!(neighbors(&state).len(), 6);
+/// // Only tube 1 into tube 2 and the two pours of orange onto orange are left
+/// assert_eq!(pruned_neighbors(&state, None, Rules::WholeBlock).len(), 3);
+/// ```
+pub fn pruned_neighbors(
+    state: &TubeState,
+    previous: Option<&TransferAction>,
+    rules: Rules,
+) -> Vec<(TransferAction, TubeState)> {
+    return neighbors_with_rules(state, rules)
+        .into_iter()
+        .filter(|(action, _)| !is_pruned(state, action, previous))
+        .collect();
+}
+
+/// Whether `pruned_neighbors` skips making `action` from `state` after `previous`
+///
+/// The check on `previous` is the same whichever of the two moves comes first, so a
+/// search going backward can pass the move it came from instead.
+pub(crate) fn is_pruned(
+    state: &TubeState,
+    action: &TransferAction,
+    previous: Option<&TransferAction>,
+) -> bool {
+    let send_tube = &state.tubes[action.send_idx as usize];
+    let recv_tube = &state.tubes[action.recv_idx as usize];
+    if recv_tube.isempty() {
+        if send_tube.ismonochrome() && send_tube.capacity() == recv_tube.capacity() {
+            return true;
+        }
+        let first_empty = state
+            .tubes
+            .iter()
+            .position(|t| t.isempty() && t.capacity() == recv_tube.capacity());
+        if first_empty != Some(action.recv_idx as usize) {
+            return true;
+        }
+    }
+    if let Some(previous) = previous {
+        if previous.send_idx == action.recv_idx && previous.recv_idx == action.send_idx {
+            return true;
+        }
+    }
+    return false;
+}
+
+#[cfg(test)]
+mod pruning_tests {
+    use super::*;
+    use crate::{load_level, LiquidColor, SearchConfig, Solver, Tube};
+    use crate::{neighbors, AStar, Bfs, IdaStar};
+
+    #[test]
+    fn test_monochrome_into_empty_is_pruned() {
+        let state = TubeState {
+            tubes: vec![
+                Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Orange]),
+                Tube::from_vec(vec![]),
+            ],
+        };
+        assert_eq!(neighbors(&state).len(), 1);
+        assert_eq!(pruned_neighbors(&state, None, Rules::WholeBlock).len(), 0);
+    }
+
+    #[test]
+    fn test_only_first_empty_tube_is_used() {
+        let state = TubeState {
+            tubes: vec![
+                Tube::from_vec(vec![]),
+                Tube::from_vec(vec![LiquidColor::Blue, LiquidColor::Orange]),
+                Tube::from_vec(vec![]),
+            ],
+        };
+        let pruned = pruned_neighbors(&state, None, Rules::WholeBlock);
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].0.recv_idx, 0);
+    }
+
+    #[test]
+    fn test_reversing_previous_pour_is_pruned() {
+        let state = TubeState {
+            tubes: vec![
+                Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Blue]),
+                Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Red]),
+            ],
+        };
+        let previous = TransferAction {
+            send_idx: 1,
+            recv_idx: 0,
+        };
+        assert_eq!(pruned_neighbors(&state, None, Rules::WholeBlock).len(), 2);
+        let pruned = pruned_neighbors(&state, Some(&previous), Rules::WholeBlock);
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].0.send_idx, 1);
+    }
+
+    #[test]
+    fn test_pruning_keeps_optimal_lengths_on_every_level() {
+        let mut paths: Vec<_> = std::fs::read_dir("levels")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+        let pruned = SearchConfig::default();
+        let unpruned = SearchConfig {
+            prune_moves: false,
+            ..SearchConfig::default()
+        };
+        assert!(pruned.prune_moves);
+        let solvers: Vec<Box<dyn Solver>> = vec![Box::new(Bfs), Box::new(AStar), Box::new(IdaStar)];
+        for path in paths {
+            let state = load_level(path.to_str().unwrap());
+            // The solvers need to know every color, see one_move_hidden_hint for the rest
+            if state.hidden_count() > 0 {
+                continue;
+            }
+            let expected = Bfs.solve(&state, &unpruned).result.unwrap().actions.len();
+            for solver in &solvers {
+                let report = solver.solve(&state, &pruned);
+                let solution = report.result.unwrap();
+                assert!(solution.state.issolved());
+                assert_eq!(
+                    solution.actions.len(),
+                    expected,
+                    "{} on {}",
+                    solver.name(),
+                    path.display()
+                );
+                let unpruned_report = solver.solve(&state, &unpruned);
+                assert!(report.stats.nodes_generated <= unpruned_report.stats.nodes_generated);
+            }
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use crate::{try_transfer, LiquidColor, Rules, TransferAction, TubeState};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Why a transfer isn't allowed by the rules of the game
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum MoveError {
+    /// The move names a tube the state doesn't have
+    NoSuchTube(usize),
+    /// The move pours a tube into itself
+    SameTube,
+    /// There is no liquid to pour
+    EmptySource,
+    /// The liquid poured and the top of the receiving tube are different colors
+    ColorMismatch {
+        poured: LiquidColor,
+        top: LiquidColor,
+    },
+    /// The receiving tube can't hold all of the liquid poured
+    NotEnoughRoom { poured: i32, room: i32 },
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MoveError::NoSuchTube(idx) => write!(f, "there is no tube {}", idx),
+            MoveError::SameTube => write!(f, "a tube can't be poured into itself"),
+            MoveError::EmptySource => write!(f, "the sending tube is empty"),
+            MoveError::ColorMismatch { poured, top } => {
+                write!(f, "{} can't be poured onto {}", poured, top)
+            }
+            MoveError::NotEnoughRoom { poured, room } => write!(
+                f,
+                "{} layers are poured but the receiving tube only has room for {}",
+                poured, room
+            ),
+        }
+    }
+}
+
+impl Error for MoveError {}
+
+/// The first illegal move found while replaying a list of moves
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ReplayError {
+    /// Where the move is in the list, counting from zero
+    pub move_idx: usize,
+    pub action: TransferAction,
+    pub reason: MoveError,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "move {} ({}) is illegal: {}",
+            self.move_idx + 1,
+            self.action,
+            self.reason
+        )
+    }
+}
+
+impl Error for ReplayError {}
+
+impl TransferAction {
+    pub fn new(send_idx: usize, recv_idx: usize) -> TransferAction {
+        return TransferAction {
+            send_idx: send_idx as i32,
+            recv_idx: recv_idx as i32,
+        };
+    }
+
+    pub fn send_idx(&self) -> usize {
+        return self.send_idx as usize;
+    }
+
+    pub fn recv_idx(&self) -> usize {
+        return self.recv_idx as usize;
+    }
+}
+
+impl FromStr for TransferAction {
+    type Err = String;
+
+    /// Parses a move written the way `{:#}` displays it, like `0->3`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_idx = |idx: &str| {
+            return idx
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| format!("{} is not a move, expected something like 0->3", s));
+        };
+        match s.split_once("->") {
+            Some((send, recv)) => {
+                return Ok(TransferAction::new(parse_idx(send)?, parse_idx(recv)?))
+            }
+            None => return Err(format!("{} is not a move, expected something like 0->3", s)),
+        }
+    }
+}
+
+/// Parses a list of moves like `0->3, 1->4`
+///
+/// Moves can be separated by commas, spaces or new lines, and everything after a `#` on
+/// a line is a comment. Each move is written the way `{:#}` displays it.
+///
+/// ```
+/// use tubes::{parse_moves, TransferAction};
+/// let moves = parse_moves("0->3, 1->4 # first two\n2->0").unwrap();
+/// assert_eq!(
+///     moves,
+///     vec![TransferAction::new(0, 3), TransferAction::new(1, 4), TransferAction::new(2, 0)]
+/// );
+/// ```
+pub fn parse_moves(s: &str) -> Result<Vec<TransferAction>, String> {
+    let mut actions = Vec::new();
+    for line in s.lines() {
+        let line = line.split('#').next().unwrap();
+        for word in line.split(|c: char| c == ',' || c.is_whitespace()) {
+            if !word.is_empty() {
+                actions.push(word.parse()?);
+            }
+        }
+    }
+    return Ok(actions);
+}
+
+impl TubeState {
+    /// Returns the state after making the move, or why the move isn't allowed
+    ///
+    /// ```
+    /// use tubes::{LiquidColor, MoveError, TransferAction, Tube, TubeState};
+    /// let state = TubeState {
+    ///     tubes: vec![
+    ///         Tube::from_vec(vec![LiquidColor::Blue, LiquidColor::Orange]),
+    ///         Tube::from_vec(vec![LiquidColor::Orange]),
+    ///         Tube::from_vec(vec![]),
+    ///     ],
+    /// };
+    /// assert!(state.apply(&TransferAction::new(0, 2)).is_ok());
+    /// assert_eq!(
+    ///     state.apply(&TransferAction::new(2, 0)),
+    ///     Err(MoveError::EmptySource)
+    /// );
+    /// assert_eq!(
+    ///     state.apply(&TransferAction::new(0, 1)),
+    ///     Err(MoveError::ColorMismatch { poured: LiquidColor::Blue, top: LiquidColor::Orange })
+    /// );
+    /// ```
+    pub fn apply(&self, action: &TransferAction) -> Result<TubeState, MoveError> {
+        return self.apply_with_rules(action, Rules::WholeBlock);
+    }
+
+    /// Returns the state after making the move under the given rules, or why the move
+    /// isn't allowed
+    pub fn apply_with_rules(
+        &self,
+        action: &TransferAction,
+        rules: Rules,
+    ) -> Result<TubeState, MoveError> {
+        for idx in [action.send_idx(), action.recv_idx()] {
+            if idx >= self.tubes.len() {
+                return Err(MoveError::NoSuchTube(idx));
+            }
+        }
+        if action.send_idx() == action.recv_idx() {
+            return Err(MoveError::SameTube);
+        }
+        let (send_tube, recv_tube) = try_transfer(
+            &self.tubes[action.send_idx()],
+            &self.tubes[action.recv_idx()],
+            rules,
+        )?;
+        let mut next_state = self.clone();
+        next_state.tubes[action.send_idx()] = send_tube;
+        next_state.tubes[action.recv_idx()] = recv_tube;
+        return Ok(next_state);
+    }
+}
+
+/// Makes every move in turn and returns each state along the way
+///
+/// The first state returned is `initial_state` and the last is the state after every
+/// move, so there is one more state than there are moves. If a move isn't allowed the
+/// error says which one and why.
+///
+/// ```
+/// use tubes::{replay, solve_bfs, LiquidColor, TransferAction, Tube, TubeState};
+/// let state = TubeState {
+///     tubes: vec![
+///         Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Orange, LiquidColor::Blue]),
+///         Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Orange, LiquidColor::Blue]),
+///         Tube::from_vec(vec![LiquidColor::Blue, LiquidColor::Blue]),
+///         Tube::from_vec(vec![]),
+///     ],
+/// };
+/// let solution = solve_bfs(&state).unwrap();
+/// let states = replay(&state, &solution.actions).unwrap();
+/// assert_eq!(states.len(), solution.actions.len() + 1);
+/// assert!(states.last().unwrap().issolved());
+/// let error = replay(&state, &[TransferAction::new(0, 1)]).unwrap_err();
+/// assert_eq!(error.move_idx, 0);
+/// ```
+pub fn replay(
+    initial_state: &TubeState,
+    actions: &[TransferAction],
+) -> Result<Vec<TubeState>, ReplayError> {
+    return replay_with_rules(initial_state, actions, Rules::WholeBlock);
+}
+
+/// Makes every move in turn under the given rules and returns each state along the way
+pub fn replay_with_rules(
+    initial_state: &TubeState,
+    actions: &[TransferAction],
+    rules: Rules,
+) -> Result<Vec<TubeState>, ReplayError> {
+    let mut states = vec![initial_state.clone()];
+    for (move_idx, action) in actions.iter().enumerate() {
+        let next_state = states
+            .last()
+            .unwrap()
+            .apply_with_rules(action, rules)
+            .map_err(|reason| ReplayError {
+                move_idx,
+                action: *action,
+                reason,
+            })?;
+        states.push(next_state);
+    }
+    return Ok(states);
+}
+
+#[cfg(test)]
+mod replay_tests {
+    use super::*;
+    use crate::{load_level, neighbors, Tube};
+
+    #[test]
+    fn test_apply_matches_neighbors() {
+        let state = load_level("levels/6.toml");
+        let legal = neighbors(&state);
+        for send_idx in 0..state.tubes.len() {
+            for recv_idx in 0..state.tubes.len() {
+                let action = TransferAction::new(send_idx, recv_idx);
+                let expected = legal.iter().find(|(a, _)| a == &action).map(|(_, s)| s);
+                assert_eq!(state.apply(&action).ok().as_ref(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_illegal_move_reasons() {
+        let state = TubeState {
+            tubes: vec![
+                Tube::from_vec(vec![LiquidColor::Blue, LiquidColor::Blue, LiquidColor::Red]),
+                Tube::from_vec(vec![
+                    LiquidColor::Blue,
+                    LiquidColor::Red,
+                    LiquidColor::Red,
+                    LiquidColor::Blue,
+                ]),
+                Tube::from_vec(vec![LiquidColor::Red, LiquidColor::Red, LiquidColor::Blue]),
+                Tube::from_vec(vec![]),
+            ],
+        };
+        assert_eq!(
+            state.apply(&TransferAction::new(0, 4)),
+            Err(MoveError::NoSuchTube(4))
+        );
+        assert_eq!(
+            state.apply(&TransferAction::new(1, 1)),
+            Err(MoveError::SameTube)
+        );
+        assert_eq!(
+            state.apply(&TransferAction::new(3, 0)),
+            Err(MoveError::EmptySource)
+        );
+        assert_eq!(
+            state.apply(&TransferAction::new(0, 1)),
+            Err(MoveError::NotEnoughRoom { poured: 2, room: 0 })
+        );
+        assert_eq!(
+            state.apply(&TransferAction::new(0, 2)),
+            Err(MoveError::ColorMismatch {
+                poured: LiquidColor::Blue,
+                top: LiquidColor::Red
+            })
+        );
+        assert!(state.apply(&TransferAction::new(1, 0)).is_ok());
+    }
+
+    #[test]
+    fn test_parse_moves() {
+        assert_eq!("3->10".parse(), Ok(TransferAction::new(3, 10)));
+        assert_eq!(" 0 -> 1 ".parse(), Ok(TransferAction::new(0, 1)));
+        assert!("0-1".parse::<TransferAction>().is_err());
+        assert!("a->1".parse::<TransferAction>().is_err());
+        let action = TransferAction::new(4, 2);
+        assert_eq!(format!("{:#}", action).parse(), Ok(action));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::{
+    replay_with_rules, AStar, ReplayError, SearchConfig, SolveError, Solver, TransferAction,
+    TubeState,
+};
+use std::error::Error;
+use std::fmt;
+
+/// Why a list of moves couldn't be reviewed
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ReviewError {
+    /// One of the moves isn't allowed by the rules of the game
+    IllegalMove(ReplayError),
+    /// A search stopped before it could tell whether a state is winnable
+    Search(SolveError),
+}
+
+impl fmt::Display for ReviewError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReviewError::IllegalMove(e) => write!(f, "{}", e),
+            ReviewError::Search(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ReviewError {}
+
+impl From<ReplayError> for ReviewError {
+    fn from(e: ReplayError) -> Self {
+        return ReviewError::IllegalMove(e);
+    }
+}
+
+impl From<SolveError> for ReviewError {
+    fn from(e: SolveError) -> Self {
+        return ReviewError::Search(e);
+    }
+}
+
+/// Every state a game went through and whether the level could still be solved from it
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Review {
+    /// The starting state followed by the state after each move
+    pub states: Vec<TubeState>,
+    /// Whether each state in `states` can still be solved
+    pub winnable: Vec<bool>,
+}
+
+impl Review {
+    /// Returns the index of the move that left the level unwinnable
+    ///
+    /// This is `None` if the level can still be solved after the last move, and also if
+    /// it couldn't be solved to begin with.
+    pub fn dead_end(&self) -> Option<usize> {
+        let first_lost = self.winnable.iter().position(|w| !w)?;
+        return first_lost.checked_sub(1);
+    }
+
+    /// Returns how many moves were made after the last state the level could be solved
+    /// from, which is zero if it can still be solved
+    pub fn moves_since_winnable(&self) -> usize {
+        return match self.winnable.iter().rposition(|w| *w) {
+            Some(last_winnable) => self.winnable.len() - 1 - last_winnable,
+            None => self.winnable.len() - 1,
+        };
+    }
+}
+
+/// Returns true if some sequence of moves solves the state
+fn is_winnable(state: &TubeState, config: &SearchConfig) -> Result<bool, SolveError> {
+    match AStar.solve(state, config).result {
+        Ok(_) => return Ok(true),
+        Err(SolveError::Unsolvable) => return Ok(false),
+        Err(e) => return Err(e),
+    }
+}
+
+/// Replays the moves a player made and labels every state along the way as winnable or not
+///
+/// Once a level can't be solved no move can make it solvable again, so the states are
+/// winnable up to some move and lost after it. Only the states a binary search visits
+/// are solved, which keeps long games quick to review. The moves follow the rules in the
+/// config, and the config applies to each of those searches.
+///
+/// ```
+/// use tubes::{review_moves, LiquidColor, SearchConfig, TransferAction, Tube, TubeState};
+/// let state = TubeState {
+///     tubes: vec![
+///         Tube::from_vec(vec![]),
+///         Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Blue, LiquidColor::Orange, LiquidColor::Orange]),
+///         Tube::from_vec(vec![LiquidColor::Blue, LiquidColor::Blue, LiquidColor::Blue, LiquidColor::Orange]),
+///     ],
+/// };
+/// // After pouring the orange into the empty tube no more moves can be made
+/// let review = review_moves(&state, &[TransferAction::new(1, 0)], &SearchConfig::default()).unwrap();
+/// assert_eq!(review.winnable, vec![true, false]);
+/// assert_eq!(review.dead_end(), Some(0));
+/// ```
+pub fn review_moves(
+    initial_state: &TubeState,
+    actions: &[TransferAction],
+    config: &SearchConfig,
+) -> Result<Review, ReviewError> {
+    let states = replay_with_rules(initial_state, actions, config.rules)?;
+    // Find the first lost state, knowing everything before `low` is winnable and
+    // everything from `high` on is lost
+    let mut low = 0;
+    let mut high = states.len();
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if is_winnable(&states[mid], config)? {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    let winnable = (0..states.len()).map(|i| i < low).collect();
+    return Ok(Review { states, winnable });
+}
+
+#[cfg(test)]
+mod review_tests {
+    use super::*;
+    use crate::{load_level, neighbors, solve_bfs, Bfs};
+
+    #[test]
+    fn test_solution_is_always_winnable() {
+        let state = load_level("levels/3.toml");
+        let solution = solve_bfs(&state).unwrap();
+        let review = review_moves(&state, &solution.actions, &SearchConfig::default()).unwrap();
+        assert!(review.winnable.iter().all(|w| *w));
+        assert_eq!(review.dead_end(), None);
+        assert_eq!(review.moves_since_winnable(), 0);
+    }
+
+    #[test]
+    fn test_labels_match_solving_every_state() {
+        let config = SearchConfig::default();
+        for level in 2..=5 {
+            // Always take the last legal move that doesn't undo the one before it, which
+            // tends to fill up the empty tubes with the wrong colors
+            let mut state = load_level(&format!("levels/{}.toml", level));
+            let initial_state = state.clone();
+            let mut actions: Vec<TransferAction> = Vec::new();
+            while actions.len() < 20 && !state.issolved() {
+                let undo = actions
+                    .last()
+                    .map(|a| TransferAction::new(a.recv_idx(), a.send_idx()));
+                let next = neighbors(&state)
+                    .into_iter()
+                    .rev()
+                    .find(|(a, _)| Some(*a) != undo);
+                match next {
+                    Some((action, next_state)) => {
+                        actions.push(action);
+                        state = next_state;
+                    }
+                    None => break,
+                }
+            }
+            let review = review_moves(&initial_state, &actions, &config).unwrap();
+            for (state, winnable) in review.states.iter().zip(&review.winnable) {
+                assert_eq!(Bfs.solve(state, &config).result.is_ok(), *winnable);
+            }
+            if let Some(dead_end) = review.dead_end() {
+                assert!(review.winnable[dead_end]);
+                assert!(!review.winnable[dead_end + 1]);
+                assert_eq!(review.moves_since_winnable(), actions.len() - dead_end);
+            }
+        }
+    }
+
+    #[test]
+    fn test_illegal_move_is_reported() {
+        let state = load_level("levels/3.toml");
+        let actions = [TransferAction::new(0, 0)];
+        assert!(matches!(
+            review_moves(&state, &actions, &SearchConfig::default()),
+            Err(ReviewError::IllegalMove(_))
+        ));
+    }
+}
@@ -0,0 +1,116 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Which version of the rules of the game a transfer follows
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Rules {
+    /// The whole top block of liquid has to fit in the receiving tube or nothing is poured
+    #[default]
+    WholeBlock,
+    /// As much of the top block as fits is poured and the rest stays behind, like the
+    /// Water Sort game on phones
+    PartialPour,
+}
+
+impl Rules {
+    pub const ALL: [Rules; 2] = [Rules::WholeBlock, Rules::PartialPour];
+
+    pub fn name(&self) -> &str {
+        match self {
+            Rules::WholeBlock => return "whole-block",
+            Rules::PartialPour => return "partial-pour",
+        }
+    }
+}
+
+impl fmt::Display for Rules {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Rules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        for rules in Rules::ALL {
+            if rules.name() == s {
+                return Ok(rules);
+            }
+        }
+        let names: Vec<&str> = Rules::ALL.iter().map(|r| r.name()).collect();
+        return Err(format!(
+            "unknown rules {}, expected one of: {}",
+            s,
+            names.join(", ")
+        ));
+    }
+}
+
+#[cfg(test)]
+mod rules_tests {
+    use super::*;
+    use crate::{load_level, neighbors_with_rules, Algorithm, SearchConfig};
+
+    #[test]
+    fn test_rules_names() {
+        for rules in Rules::ALL {
+            assert_eq!(rules.name().parse(), Ok(rules));
+        }
+        assert!("house-rules".parse::<Rules>().is_err());
+        assert_eq!(Rules::default(), Rules::WholeBlock);
+    }
+
+    #[test]
+    fn test_partial_pours_are_never_longer() {
+        let whole_block = SearchConfig::default();
+        let partial_pour = SearchConfig {
+            rules: Rules::PartialPour,
+            ..SearchConfig::default()
+        };
+        let unpruned = SearchConfig {
+            prune_moves: false,
+            ..partial_pour.clone()
+        };
+        for level in 1..=7 {
+            let state = load_level(&format!("levels/{}.toml", level));
+            // Every whole block pour is also a partial pour
+            let whole = neighbors_with_rules(&state, Rules::WholeBlock);
+            let partial = neighbors_with_rules(&state, Rules::PartialPour);
+            assert!(whole.iter().all(|n| partial.contains(n)));
+            let whole_length = Algorithm::Bfs
+                .solver()
+                .solve(&state, &whole_block)
+                .result
+                .unwrap()
+                .actions
+                .len();
+            let expected = Algorithm::Bfs
+                .solver()
+                .solve(&state, &unpruned)
+                .result
+                .unwrap()
+                .actions
+                .len();
+            assert!(expected <= whole_length);
+            for algorithm in Algorithm::ALL {
+                let solver = algorithm.solver();
+                let solution = solver.solve(&state, &partial_pour).result.unwrap();
+                let states =
+                    crate::replay_with_rules(&state, &solution.actions, Rules::PartialPour)
+                        .unwrap();
+                assert_eq!(states.last(), Some(&solution.state));
+                assert!(solution.state.issolved());
+                if algorithm.finds_shortest() {
+                    assert_eq!(
+                        solution.actions.len(),
+                        expected,
+                        "{} on level {}",
+                        solver.name(),
+                        level
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,436 @@
+use crate::{
+    neighbors_with_rules, pruned_neighbors, AStar, Anytime, BeamSearch, Bfs, Bidirectional, Dfs,
+    ExternalBfs, GreedyBestFirst, IdaStar, ParallelBfs, Rules, TransferAction, TubeState,
+    TubeStateNode, UniformCost,
+};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Limits on how much work a solver is allowed to do before giving up
+///
+/// The default configuration has no limits, so the search runs until it finds a solution
+/// or proves there is none.
+///
+/// ```
+/// use std::time::{Duration, Instant};
+/// use tubes::SearchConfig;
+/// let config = SearchConfig {
+///     max_nodes: Some(100_000),
+///     deadline: Some(Instant::now() + Duration::from_secs(5)),
+///     ..SearchConfig::default()
+/// };
+/// assert!(config.cancel.is_none());
+/// ```
+#[derive(Clone)]
+pub struct SearchConfig {
+    /// The most states the search may expand
+    pub max_nodes: Option<usize>,
+    /// The wall clock time at which the search gives up
+    pub deadline: Option<Instant>,
+    /// A flag another thread can set to stop the search early
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Called with the current statistics every `PROGRESS_INTERVAL` expanded states and
+    /// once more when the search ends
+    pub progress: Option<ProgressCallback>,
+    /// Skip moves that can never be part of a shortest solution, see `pruned_neighbors`
+    pub prune_moves: bool,
+    /// Which version of the rules of the game moves follow
+    pub rules: Rules,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        return SearchConfig {
+            max_nodes: None,
+            deadline: None,
+            cancel: None,
+            progress: None,
+            prune_moves: true,
+            rules: Rules::WholeBlock,
+        };
+    }
+}
+
+/// A function that is given the statistics of a running search
+pub type ProgressCallback = Arc<dyn Fn(&SearchStats) + Send + Sync>;
+
+/// How many states are expanded between calls to the progress callback
+pub const PROGRESS_INTERVAL: usize = 10_000;
+
+impl fmt::Debug for SearchConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SearchConfig")
+            .field("max_nodes", &self.max_nodes)
+            .field("deadline", &self.deadline)
+            .field("cancel", &self.cancel)
+            .field("progress", &self.progress.is_some())
+            .field("prune_moves", &self.prune_moves)
+            .field("rules", &self.rules)
+            .finish()
+    }
+}
+
+impl SearchConfig {
+    /// Returns the moves the search should try from `state`, which was reached by the
+    /// move `previous`
+    pub(crate) fn successors(
+        &self,
+        state: &TubeState,
+        previous: Option<&TransferAction>,
+    ) -> Vec<(TransferAction, TubeState)> {
+        if self.prune_moves {
+            return pruned_neighbors(state, previous, self.rules);
+        }
+        return neighbors_with_rules(state, self.rules);
+    }
+
+    /// Returns an error if the search has to stop after expanding `nodes_expanded` states
+    pub(crate) fn check(&self, nodes_expanded: usize) -> Result<(), SolveError> {
+        if let Some(cancel) = &self.cancel {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(SolveError::SearchLimit(Limit::Cancelled));
+            }
+        }
+        if let Some(max_nodes) = self.max_nodes {
+            if nodes_expanded >= max_nodes {
+                return Err(SolveError::SearchLimit(Limit::Nodes(max_nodes)));
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(SolveError::SearchLimit(Limit::Time));
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// The search limit that stopped a solver
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Limit {
+    /// The given number of states were expanded
+    Nodes(usize),
+    /// The deadline passed
+    Time,
+    /// The cancellation flag was set
+    Cancelled,
+    /// The hidden layers of a state could be filled in more than the given number of ways
+    Possibilities(usize),
+    /// Every state kept in a beam of the given width ran out of new moves
+    Beam(usize),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Nodes(max_nodes) => write!(f, "{} states were expanded", max_nodes),
+            Limit::Time => write!(f, "the time limit ran out"),
+            Limit::Cancelled => write!(f, "the search was cancelled"),
+            Limit::Possibilities(max_possibilities) => write!(
+                f,
+                "the hidden layers could be filled in more than {} ways",
+                max_possibilities
+            ),
+            Limit::Beam(width) => {
+                write!(f, "every state in a beam of {} ran out of new moves", width)
+            }
+        }
+    }
+}
+
+/// The reasons a solver can fail to return a solution
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SolveError {
+    /// Every reachable state was explored and none of them are solved
+    Unsolvable,
+    /// The search gave up before it could find a solution or prove there is none
+    SearchLimit(Limit),
+    /// The starting state can never be solved because of how it was set up
+    InvalidInput(String),
+    /// The files a search keeps its states in couldn't be written or read
+    Storage(String),
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SolveError::Unsolvable => {
+                write!(f, "no solution exists, every reachable state was explored")
+            }
+            SolveError::SearchLimit(limit) => {
+                write!(f, "gave up before finding a solution because {}", limit)
+            }
+            SolveError::InvalidInput(reason) => write!(f, "invalid tubes: {}", reason),
+            SolveError::Storage(reason) => {
+                write!(
+                    f,
+                    "could not use the files the search is stored in: {}",
+                    reason
+                )
+            }
+        }
+    }
+}
+
+impl Error for SolveError {}
+
+impl From<io::Error> for SolveError {
+    fn from(e: io::Error) -> Self {
+        return SolveError::Storage(e.to_string());
+    }
+}
+
+/// Counters describing how much work a solver did
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SearchStats {
+    /// The number of states whose neighbors were generated
+    pub nodes_expanded: usize,
+    /// The number of neighboring states that were generated
+    pub nodes_generated: usize,
+    /// The most states that were waiting to be expanded at once
+    pub peak_frontier: usize,
+    /// The number of generated states that were dropped because they were already seen
+    pub duplicates_pruned: usize,
+    /// The most transfers made to reach an expanded state
+    pub max_depth: usize,
+    /// The time from the start of the search until the stats were taken
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for SearchStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "expanded {} states, generated {}, pruned {} duplicates, peak frontier {}, depth {}, {:.3}s",
+            self.nodes_expanded,
+            self.nodes_generated,
+            self.duplicates_pruned,
+            self.peak_frontier,
+            self.max_depth,
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+/// What a `Solver` returns: the solution or the reason there isn't one, plus statistics
+#[derive(Debug)]
+pub struct SearchReport {
+    pub result: Result<TubeStateNode, SolveError>,
+    pub stats: SearchStats,
+    /// The fewest transfers any solution can take, for solvers that don't always find the
+    /// shortest one
+    pub lower_bound: Option<usize>,
+}
+
+impl SearchReport {
+    /// Returns how many more transfers the solution takes than `lower_bound`, so a gap
+    /// of 0 means the solution is known to be one of the shortest
+    pub fn gap(&self) -> Option<usize> {
+        let solution = self.result.as_ref().ok()?;
+        return self
+            .lower_bound
+            .map(|bound| solution.actions.len().saturating_sub(bound));
+    }
+}
+
+/// A strategy for solving the game of tubes
+///
+/// ```
+/// use tubes::{AStar, Bfs, SearchConfig, Solver, Tube, TubeState, LiquidColor};
+/// let state = TubeState {
+///     tubes: vec![
+///         Tube::from_vec(vec![LiquidColor::Orange]),
+///         Tube::from_vec(vec![LiquidColor::Orange, LiquidColor::Orange, LiquidColor::Orange]),
+///     ],
+/// };
+/// let solvers: Vec<Box<dyn Solver>> = vec![Box::new(Bfs), Box::new(AStar)];
+/// for solver in solvers {
+///     let report = solver.solve(&state, &SearchConfig::default());
+///     assert_eq!(report.result.unwrap().actions.len(), 1);
+/// }
+/// ```
+pub trait Solver {
+    /// A short name for the strategy
+    fn name(&self) -> &str;
+
+    /// Searches for a sequence of transfers that solves `initial_state`
+    fn solve(&self, initial_state: &TubeState, config: &SearchConfig) -> SearchReport;
+}
+
+/// Keeps track of the limits and statistics of a single run of a solver
+pub(crate) struct SearchContext<'a> {
+    config: &'a SearchConfig,
+    start: Instant,
+    pub stats: SearchStats,
+    pub lower_bound: Option<usize>,
+}
+
+impl<'a> SearchContext<'a> {
+    pub fn new(config: &'a SearchConfig) -> SearchContext<'a> {
+        return SearchContext {
+            config,
+            start: Instant::now(),
+            stats: SearchStats::default(),
+            lower_bound: None,
+        };
+    }
+
+    /// Records that a state `depth` transfers deep is about to be expanded, or fails if a
+    /// limit has been hit
+    pub fn expand(&mut self, depth: usize) -> Result<(), SolveError> {
+        self.config.check(self.stats.nodes_expanded)?;
+        self.stats.nodes_expanded += 1;
+        self.stats.max_depth = self.stats.max_depth.max(depth);
+        if self.stats.nodes_expanded % PROGRESS_INTERVAL == 0 {
+            self.report_progress();
+        }
+        return Ok(());
+    }
+
+    /// Records work that was done outside of `expand`, for example by worker threads
+    /// that check the limits in the config themselves
+    pub fn record(&mut self, expanded: usize, generated: usize, duplicates: usize) {
+        let intervals_before = self.stats.nodes_expanded / PROGRESS_INTERVAL;
+        self.stats.nodes_expanded += expanded;
+        self.stats.nodes_generated += generated;
+        self.stats.duplicates_pruned += duplicates;
+        if self.stats.nodes_expanded / PROGRESS_INTERVAL != intervals_before {
+            self.report_progress();
+        }
+    }
+
+    /// Records that states `depth` transfers deep were expanded
+    pub fn expanded_to_depth(&mut self, depth: usize) {
+        self.stats.max_depth = self.stats.max_depth.max(depth);
+    }
+
+    pub fn config(&self) -> &'a SearchConfig {
+        return self.config;
+    }
+
+    /// Returns the moves the search should try from `state`, see `SearchConfig::successors`
+    pub fn successors(
+        &self,
+        state: &TubeState,
+        previous: Option<&TransferAction>,
+    ) -> Vec<(TransferAction, TubeState)> {
+        return self.config.successors(state, previous);
+    }
+
+    /// Records that a neighboring state was generated
+    pub fn generated(&mut self) {
+        self.stats.nodes_generated += 1;
+    }
+
+    /// Records that a generated state was dropped because it was already seen
+    pub fn duplicate(&mut self) {
+        self.stats.duplicates_pruned += 1;
+    }
+
+    /// Records the number of states waiting to be expanded
+    pub fn frontier(&mut self, size: usize) {
+        self.stats.peak_frontier = self.stats.peak_frontier.max(size);
+    }
+
+    /// Returns the statistics so far, with the time elapsed up to now
+    pub fn stats_now(&mut self) -> &SearchStats {
+        self.stats.elapsed = self.start.elapsed();
+        return &self.stats;
+    }
+
+    fn report_progress(&mut self) {
+        if let Some(progress) = &self.config.progress {
+            self.stats.elapsed = self.start.elapsed();
+            progress(&self.stats);
+        }
+    }
+
+    pub fn finish(mut self, result: Result<TubeStateNode, SolveError>) -> SearchReport {
+        self.report_progress();
+        self.stats.elapsed = self.start.elapsed();
+        return SearchReport {
+            result,
+            stats: self.stats,
+            lower_bound: self.lower_bound,
+        };
+    }
+}
+
+/// The solvers that can be picked by name, for example from the command line
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Algorithm {
+    Bfs,
+    Dfs,
+    AStar,
+    IdaStar,
+    ParallelBfs,
+    Bidirectional,
+    ExternalBfs,
+    Beam,
+    Greedy,
+    Anytime,
+    UniformCost,
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 11] = [
+        Algorithm::Bfs,
+        Algorithm::Dfs,
+        Algorithm::AStar,
+        Algorithm::IdaStar,
+        Algorithm::ParallelBfs,
+        Algorithm::Bidirectional,
+        Algorithm::ExternalBfs,
+        Algorithm::Beam,
+        Algorithm::Greedy,
+        Algorithm::Anytime,
+        Algorithm::UniformCost,
+    ];
+
+    pub fn solver(&self) -> Box<dyn Solver> {
+        match self {
+            Algorithm::Bfs => Box::new(Bfs),
+            Algorithm::Dfs => Box::new(Dfs),
+            Algorithm::AStar => Box::new(AStar),
+            Algorithm::IdaStar => Box::new(IdaStar),
+            Algorithm::ParallelBfs => Box::new(ParallelBfs::default()),
+            Algorithm::Bidirectional => Box::new(Bidirectional),
+            Algorithm::ExternalBfs => Box::new(ExternalBfs::default()),
+            Algorithm::Beam => Box::new(BeamSearch::default()),
+            Algorithm::Greedy => Box::new(GreedyBestFirst::default()),
+            Algorithm::Anytime => Box::new(Anytime::default()),
+            Algorithm::UniformCost => Box::new(UniformCost::default()),
+        }
+    }
+
+    /// Whether the solver always returns one of the shortest solutions
+    pub fn finds_shortest(&self) -> bool {
+        return !matches!(self, Algorithm::Dfs | Algorithm::Beam | Algorithm::Greedy);
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        for algorithm in Algorithm::ALL {
+            if algorithm.solver().name() == s {
+                return Ok(algorithm);
+            }
+        }
+        let names: Vec<String> = Algorithm::ALL
+            .iter()
+            .map(|a| a.solver().name().to_string())
+            .collect();
+        return Err(format!(
+            "unknown algorithm {}, expected one of: {}",
+            s,
+            names.join(", ")
+        ));
+    }
+}